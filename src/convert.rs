@@ -0,0 +1,299 @@
+use super::*;
+
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+impl ConverterSchema {
+    /// Converts a value from a raw document into the form it's stored and searched in.
+    pub fn apply(&self, field: &str, value: &Value) -> Result<Value, CompassError> {
+        let err = |reason: &str| {
+            CompassError::ConversionError(format!(
+                "field '{}': {} (from {:?} to {:?})",
+                field, reason, self.from, self.to
+            ))
+        };
+
+        if value.is_null() {
+            return Ok(Value::Null);
+        }
+
+        match (self.from, self.to) {
+            (ConvertFrom::CommaSeparatedString, ConvertTo::TagArray)
+            | (ConvertFrom::SemicolonSeparatedString, ConvertTo::TagArray) => {
+                if value.is_array() {
+                    // already split up
+                    return Ok(value.clone());
+                }
+
+                let separator = match self.from {
+                    ConvertFrom::CommaSeparatedString => ',',
+                    _ => ';',
+                };
+
+                let s = value.as_str().ok_or_else(|| err("expected a string"))?;
+                Ok(json!(s
+                    .split(separator)
+                    .map(|x| x.trim())
+                    .filter(|x| !x.is_empty())
+                    .collect::<Vec<&str>>()))
+            }
+            (ConvertFrom::DateTimeString, ConvertTo::Timestamp)
+            | (ConvertFrom::DateTimeString, ConvertTo::TimestampMillis) => {
+                let s = value.as_str().ok_or_else(|| err("expected a string"))?;
+                let dt = DateTime::parse_from_rfc3339(s)
+                    .map_err(|e| err(&e.to_string()))?
+                    .with_timezone(&Utc);
+                Ok(self.timestamp(dt))
+            }
+            (ConvertFrom::DateString, ConvertTo::Timestamp)
+            | (ConvertFrom::DateString, ConvertTo::TimestampMillis) => {
+                let s = value.as_str().ok_or_else(|| err("expected a string"))?;
+                let date =
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| err(&e.to_string()))?;
+                Ok(self.timestamp(Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN))))
+            }
+            _ => Err(err("unsupported conversion")),
+        }
+    }
+
+    /// Converts a value from a URL parameter into the form it's stored in. For timestamp fields, that means dates (`2021-03-01`), datetimes (`2021-03-01T12:00:00Z`, UTC if there's no offset) and times relative to now (`now`, `now-7d`, `now+12h`) turn into timestamps.
+    /// Anything else, like raw timestamps, `exists` or aliases, is passed through as is.
+    pub fn query_value(&self, value: &str) -> Result<String, CompassError> {
+        match self.to {
+            ConvertTo::Timestamp | ConvertTo::TimestampMillis => {}
+            ConvertTo::TagArray => return Ok(value.to_owned()),
+        }
+
+        if value.parse::<i64>().is_ok() {
+            return Ok(value.to_owned());
+        }
+
+        let dt = if let Some(offset) = value.strip_prefix("now") {
+            relative_offset(offset)
+                .and_then(|d| Utc::now().checked_add_signed(d))
+                .ok_or_else(|| {
+                    CompassError::ConversionError(format!("invalid relative time '{}'", value))
+                })?
+        } else if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+            dt.with_timezone(&Utc)
+        } else if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+            Utc.from_utc_datetime(&dt)
+        } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN))
+        } else {
+            return Ok(value.to_owned());
+        };
+
+        Ok(self.timestamp(dt).to_string())
+    }
+
+    fn timestamp(&self, dt: DateTime<Utc>) -> Value {
+        match self.to {
+            ConvertTo::TimestampMillis => json!(dt.timestamp_millis()),
+            _ => json!(dt.timestamp()),
+        }
+    }
+
+    /// Turns a stored value back into what clients expect to see. Anything that isn't a timestamp `apply` could have stored is left as it is.
+    pub fn revert(&self, field: &mut Value) {
+        let dt = match (self.to, field.as_i64()) {
+            (ConvertTo::Timestamp, Some(n)) => Utc.timestamp_opt(n, 0).single(),
+            (ConvertTo::TimestampMillis, Some(n)) => Utc.timestamp_millis_opt(n).single(),
+            _ => None,
+        };
+
+        match (self.from, dt) {
+            (ConvertFrom::DateTimeString, Some(dt)) => {
+                *field = json!(dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
+            }
+            (ConvertFrom::DateString, Some(dt)) => {
+                *field = json!(dt.format("%Y-%m-%d").to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+// the `-7d` in `now-7d`. a `+` might have been decoded into a space on its way through the URL
+fn relative_offset(s: &str) -> Option<Duration> {
+    if s.is_empty() {
+        return Some(Duration::zero());
+    }
+
+    let (negative, rest) = match s.chars().next()? {
+        '-' => (true, &s[1..]),
+        '+' | ' ' => (false, &s[1..]),
+        _ => return None,
+    };
+
+    let unit = rest.chars().last()?;
+    let digits = &rest[..rest.len() - unit.len_utf8()];
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // chrono panics on durations too big to represent, so keep them to something sensible
+    let amount = digits.parse::<i64>().ok().filter(|n| *n <= 1_000_000)?;
+
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let seconds = amount.checked_mul(seconds)?;
+
+    Some(Duration::seconds(if negative { -seconds } else { seconds }))
+}
+
+// make a table of field -> converter, to see if we need to do any conversions on the results
+pub(crate) fn converters(schema: &Schema) -> HashMap<String, ConverterSchema> {
+    schema
+        .fields
+        .iter()
+        .filter_map(|(k, v)| v.converter.map(|converter| (k.to_owned(), converter)))
+        .collect()
+}
+
+/// Applies each field's converter to a raw document, in place.
+pub(crate) fn apply_converters(
+    converters: &HashMap<String, ConverterSchema>,
+    doc: &mut Value,
+) -> Result<(), CompassError> {
+    for (key, conv) in converters.iter() {
+        if let Some(field) = doc.get_mut(key) {
+            *field = conv.apply(key, field)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converter(from: ConvertFrom, to: ConvertTo) -> ConverterSchema {
+        ConverterSchema { from, to }
+    }
+
+    fn query(value: &str) -> Result<String, CompassError> {
+        converter(ConvertFrom::DateTimeString, ConvertTo::Timestamp).query_value(value)
+    }
+
+    fn seconds_from_now(value: &str) -> i64 {
+        query(value).unwrap().parse::<i64>().unwrap() - Utc::now().timestamp()
+    }
+
+    #[test]
+    fn query_values_parse_dates_and_datetimes() {
+        assert_eq!(query("2021-03-01").unwrap(), "1614556800");
+        assert_eq!(query("2021-03-01T12:00:00Z").unwrap(), "1614600000");
+        assert_eq!(query("2021-03-01T12:00:00+02:00").unwrap(), "1614592800");
+        assert_eq!(query("2021-03-01T12:00:00").unwrap(), "1614600000");
+        assert_eq!(
+            converter(ConvertFrom::DateString, ConvertTo::TimestampMillis)
+                .query_value("2021-03-01")
+                .unwrap(),
+            "1614556800000"
+        );
+    }
+
+    #[test]
+    fn query_values_pass_other_values_through() {
+        for value in ["1614556800", "-5", "exists", "2021-13-01", "yesterday"] {
+            assert_eq!(query(value).unwrap(), value);
+        }
+        let tags = converter(ConvertFrom::CommaSeparatedString, ConvertTo::TagArray);
+        assert_eq!(tags.query_value("now-1d").unwrap(), "now-1d");
+    }
+
+    #[test]
+    fn query_values_relative_to_now() {
+        for (value, offset) in [
+            ("now", 0),
+            ("now-30s", -30),
+            ("now+5m", 300),
+            // a + that came through the URL as a space
+            ("now 2h", 7200),
+            ("now-7d", -7 * 86400),
+            ("now+1w", 7 * 86400),
+        ] {
+            assert!(
+                (seconds_from_now(value) - offset).abs() <= 1,
+                "{:?} is off",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn query_values_reject_bad_relative_times() {
+        for value in [
+            "now-",
+            "now-d",
+            "now7d",
+            "now-7",
+            "now-7y",
+            "now--7d",
+            "now-+7d",
+            "now+-7d",
+            "now-1.5d",
+            "now-1000001d",
+            "now+-9223372036854775807d",
+            "now--9223372036854775808d",
+            "now+99999999999999999999d",
+        ] {
+            assert!(
+                matches!(query(value), Err(CompassError::ConversionError(_))),
+                "{:?} was accepted",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        for to in [ConvertTo::Timestamp, ConvertTo::TimestampMillis] {
+            let conv = converter(ConvertFrom::DateString, to);
+            let mut value = conv.apply("date", &json!("2021-03-01")).unwrap();
+            conv.revert(&mut value);
+            assert_eq!(value, json!("2021-03-01"));
+        }
+    }
+
+    #[test]
+    fn datetimes_round_trip() {
+        for to in [ConvertTo::Timestamp, ConvertTo::TimestampMillis] {
+            let conv = converter(ConvertFrom::DateTimeString, to);
+            let mut value = conv
+                .apply("date", &json!("2021-03-01T12:30:00.000Z"))
+                .unwrap();
+            conv.revert(&mut value);
+            assert_eq!(value, json!("2021-03-01T12:30:00.000Z"));
+        }
+    }
+
+    #[test]
+    fn unexpected_stored_values_are_left_alone() {
+        let conv = converter(ConvertFrom::DateTimeString, ConvertTo::TimestampMillis);
+        for stored in [
+            json!("2021-03-01"),
+            json!(1.5),
+            json!(i64::MAX),
+            json!(null),
+        ] {
+            let mut value = stored.clone();
+            conv.revert(&mut value);
+            assert_eq!(value, stored);
+        }
+
+        let conv = converter(ConvertFrom::DateString, ConvertTo::Timestamp);
+        let mut value = json!(i64::MIN);
+        conv.revert(&mut value);
+        assert_eq!(value, json!(i64::MIN));
+    }
+}
@@ -1,293 +1,55 @@
 use super::*;
 
+use convert::converters;
+use projection::projection_expr;
+use relevance::{fulltext_matches, headlines_expr};
+use visibility::strip_unreturnable;
+
 use postgres::Client;
 
-use serde_json::{json, Value};
+use serde::Serialize;
+use serde_json::Value;
 
-use postgres::fallible_iterator::FallibleIterator;
 use postgres::types::ToSql;
 use postgres::types::Type as PostgresType;
 use postgres::{Row, Statement};
 
 use std::collections::HashMap;
 
-use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
-
 use uuid::Uuid;
 
-fn parse_query_list<F>(q: &str, filter_gen: F) -> Result<String, CompassError>
-where
-    F: Fn(&str) -> Result<String, CompassError>,
-{
-    let mut filters: Vec<String> = Vec::new();
-    let iter = q.split_inclusive('_');
-
-    let mut curr_filter = String::new();
-
-    for val in iter {
-        if val == "and_" {
-            let filter_string = curr_filter.strip_suffix('_').unwrap_or(&curr_filter);
-            let filter = filter_gen(filter_string)?;
-            curr_filter = String::new();
-            filters.push(filter);
-            filters.push("&&".to_string());
-        } else if val == "or_" {
-            let filter_string = curr_filter.strip_suffix('_').unwrap_or(&curr_filter);
-            let filter = filter_gen(filter_string)?;
-            curr_filter = String::new();
-            filters.push(filter);
-            filters.push("||".to_string());
-        } else {
-            curr_filter += val;
-        };
-    }
-
-    if !curr_filter.is_empty() {
-        let filter = filter_gen(&curr_filter)?;
-        filters.push(filter);
-    }
-
-    Ok(format!("({})", filters.join(" ")))
-}
-
-pub fn generate_one_field(
-    v: &str,
-    field: (&String, FieldQuery),
-    jsonb_filters: &mut Vec<String>,
-    other_filters: &mut Vec<String>,
-    other_bindings: &mut Vec<String>,
+pub fn generate_where(
+    schema: &Schema,
+    fields: &HashMap<String, String>,
     bind_index: usize,
-) -> Result<(), CompassError> {
-    match field.1 {
-        FieldQuery::Range {
-            min: _,
-            max: _,
-            ref aliases,
-        } => {
-            // if something gets directly found as a 'Range' query, it means someone used season=18 instead of like, season_min=16. so it actually, counter-intuitively, is like a numeric tag!
-            let filters = parse_query_list(v, |x| {
-                if x == "exists" {
-                    Ok(format!("(exists($.{}))", field.0))
-                } else if x == "notexists" {
-                    Ok(format!("(!exists($.{}))", field.0))
-                } else if let Some(n) = aliases.get(&x.to_uppercase()) {
-                    Ok(format!("($.{} == {})", field.0, n))
-                } else {
-                    Ok(format!(
-                        "($.{} == {})",
-                        field.0,
-                        x.parse::<i64>().map_err(CompassError::InvalidNumberError)?
-                    ))
-                }
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::Min => {
-            let filters = parse_query_list(v, |x| {
-                Ok(format!(
-                    "($.{} > {})",
-                    field.0,
-                    x.parse::<i64>().map_err(CompassError::InvalidNumberError)?
-                ))
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::Max => {
-            let filters = parse_query_list(v, |x| {
-                Ok(format!(
-                    "($.{} < {})",
-                    field.0,
-                    x.parse::<i64>().map_err(CompassError::InvalidNumberError)?
-                ))
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::Bool => {
-            let filters = parse_query_list(v, |x| {
-                if x == "exists" {
-                    Ok(format!("(exists($.{}))", field.0))
-                } else if x == "notexists" {
-                    Ok(format!("(!exists($.{}))", field.0))
-                } else {
-                    Ok(format!(
-                        "($.{} == {})",
-                        field.0,
-                        x.parse::<bool>().map_err(CompassError::InvalidBoolError)?
-                    ))
-                }
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::AmbiguousTag => {
-            let filters = parse_query_list(v, |x| {
-                let mut filter: Vec<String> = Vec::new();
-
-                if let Ok(n) = x.parse::<i64>() {
-                    filter.push(format!("($.{} == {})", field.0, n)); // if it looks like an int, make it an int! because we can't specificy all the metadata fields in the schema. yeah i don't like this either
-                } else if let Ok(n) = x.parse::<bool>() {
-                    filter.push(format!("($.{} == {})", field.0, n));
-                } else if x == "exists" {
-                    filter.push(format!("(exists($.{}))", field.0))
-                } else if x == "notexists" {
-                    filter.push(format!("(!exists($.{}))", field.0))
-                }
-
-                filter.push(format!("($.{} == \"{}\")", field.0, x));
-
-                Ok(format!("({})", filter.join(" || ")))
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::NumericTag { ref aliases } => {
-            let filters = parse_query_list(v, |x| {
-                if x == "exists" {
-                    Ok(format!("(exists($.{}))", field.0))
-                } else if x == "notexists" {
-                    Ok(format!("(!exists($.{}))", field.0))
-                } else if let Some(n) = aliases.get(&x.to_uppercase()) {
-                    Ok(format!(
-                        "(($.{field} == {value}) || ($.{field} == \"{value}\"))",
-                        field = field.0,
-                        value = n
-                    ))
-                } else {
-                    Ok(format!(
-                        "(($.{field} == {value}) || ($.{field} == \"{value}\"))",
-                        field = field.0,
-                        value = x.parse::<i64>().map_err(CompassError::InvalidNumberError)?
-                    ))
-                }
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::StringTag => {
-            let filters = parse_query_list(v, |x| Ok(format!("($.{} == \"{}\")", field.0, x)))?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::Nested => {
-            let filters = parse_query_list(v, |x| {
-                let mut filter: Vec<String> = Vec::new();
-
-                if let Ok(n) = x.parse::<i64>() {
-                    filter.push(format!("($.{} == {})", field.0, n)); // if it looks like an int, make it an int! because we can't specificy all the metadata fields in the schema. yeah i don't like this either
-                } else if let Ok(n) = x.parse::<bool>() {
-                    filter.push(format!("($.{} == {})", field.0, n));
-                } else if x == "exists" {
-                    filter.push(format!("(exists($.{}))", field.0))
-                } else if x == "notexists" {
-                    filter.push(format!("(!exists($.{}))", field.0))
-                }
-
-                filter.push(format!("($.{} == \"{}\")", field.0, x));
-
-                Ok(format!("({})", filter.join(" || ")))
-            })?;
-            jsonb_filters.push(filters);
-        }
-        FieldQuery::Fulltext {
-            ref lang,
-            ref syntax,
-            ref target,
-        } => {
-            other_filters.push(format!(
-                "to_tsvector('{lang}',object->>'{key}') @@ {function}('{lang}',${parameter})",
-                lang = lang,
-                key = target.as_ref().unwrap_or(field.0),
-                function = syntax,
-                parameter = other_filters.len() + bind_index
-            ));
-            other_bindings.push(v.to_string());
-        }
-        FieldQuery::Not(inner) => {
-            // i hate myself
-            let mut not_jsonb_filters = Vec::new();
-            let mut not_other_bindings = Vec::new();
-            let mut not_other_filters = Vec::new();
-            generate_one_field(
-                v,
-                (field.0, *inner),
-                &mut not_jsonb_filters,
-                &mut not_other_bindings,
-                &mut not_other_filters,
-                bind_index,
-            )?;
-
-            jsonb_filters.extend(not_jsonb_filters.into_iter().map(|v| format!("!({})", v)));
-        }
-    };
-    Ok(())
+    force_json_query: bool,
+) -> Result<(String, String, String, Vec<String>), CompassError> {
+    generate_where_params(
+        schema,
+        &SearchParams::from_fields(fields)?,
+        bind_index,
+        force_json_query,
+    )
 }
 
-pub fn generate_where(
+pub fn generate_where_params(
     schema: &Schema,
-    fields: &HashMap<String, String>,
+    params: &SearchParams,
     bind_index: usize,
     force_json_query: bool,
 ) -> Result<(String, String, String, Vec<String>), CompassError> {
-    let mut jsonb_filters = Vec::<String>::new();
-    let mut other_filters = Vec::<String>::new();
+    let (jsonb_filters, other_filters) = Query::from_params(schema, params)?.split_jsonpath();
 
     let mut other_bindings = Vec::<String>::new();
+    let other_filters: Vec<String> = other_filters
+        .iter()
+        .map(|q| q.to_sql(&mut other_bindings, bind_index))
+        .collect::<Result<Vec<String>, CompassError>>()?;
 
-    for (k, v) in fields {
-        let field_maybe = match schema.fields.get(k) {
-            // find field from URL query in schema
-            Some(field) => {
-                Some((k.clone(), field.query.clone())) // oh, we found it by name. cool, return that
-            }
-            None => {
-                let find_nested = |k: &str| {
-                    schema.fields.iter().find_map(|f| {
-                        match f.1.query {
-                            // oops we couldn't find it; let's see if it's a field that can have multiple names like range or metadata
-                            FieldQuery::Range {
-                                ref min, ref max, ..
-                            } => {
-                                if k == min {
-                                    Some((f.0.to_owned(), FieldQuery::Min))
-                                } else if k == max {
-                                    Some((f.0.to_owned(), FieldQuery::Max))
-                                } else {
-                                    None
-                                }
-                            }
-                            FieldQuery::Nested => {
-                                if k.split('.').next().unwrap() == f.0 {
-                                    Some((k.to_owned(), FieldQuery::Nested))
-                                } else {
-                                    None
-                                }
-                            }
-                            _ => None,
-                        }
-                    })
-                };
-
-                if let Some(f) = k.strip_suffix('!') {
-                    println!("{}", k);
-                    // THE GOOD CODE DETECTED (JK IT'S VERY BAD THIS IS THE WORST THING I'VE EVER WRITTEN AND I'M DYING INSIDE)
-                    schema
-                        .fields
-                        .get(f)
-                        .map(|field| (k.clone(), FieldQuery::Not(Box::new(field.query.clone()))))
-                        .or(find_nested(f).map(|(a, b)| (a, FieldQuery::Not(Box::new(b)))))
-                } else {
-                    find_nested(k)
-                }
-            }
-        };
-
-        if let Some(field) = field_maybe {
-            generate_one_field(
-                v,
-                (&field.0, field.1),
-                &mut jsonb_filters,
-                &mut other_filters,
-                &mut other_bindings,
-                bind_index,
-            )?;
-        }
-    }
+    let jsonb_filters: Vec<String> = jsonb_filters
+        .iter()
+        .filter_map(Query::to_jsonpath)
+        .collect();
 
     let json_query = format!("({})", jsonb_filters.join(" && "));
 
@@ -305,42 +67,45 @@ pub fn generate_where(
         String::new()
     };
 
-    let order = match fields.get("sortorder") {
-        Some(l) => {
-            let ord = l.as_str().to_uppercase();
-            if ord == "ASC" || ord == "DESC" {
-                ord
-            } else {
-                "ASC".to_owned()
-            }
-        }
-        None => "DESC".to_owned(),
-    };
-
     let order_string = format!(
-        " ORDER BY (object #> ($2)::text[]) {}, doc_id NULLS LAST LIMIT $3 OFFSET $4",
-        order
+        " ORDER BY {} LIMIT $2 OFFSET $3",
+        Sort::from_params(schema, params)?.order_by()
     );
 
     Ok((query, order_string, json_query, other_bindings))
 }
 
-pub fn json_search(
-    client: &mut Client,
+/// A fully built statement, ready to be prepared and run by either the blocking or the async client.
+pub(crate) struct PreparedQuery {
+    pub query: String,
+    pub types: Vec<PostgresType>,
+    pub params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl PreparedQuery {
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params
+            .iter()
+            .map(|x| &**x as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+/// A search statement, plus what's needed to turn its rows into a page.
+pub(crate) struct PreparedSearch {
+    pub prepared: PreparedQuery,
+    pub sort: Sort,
+    pub limit: i64,
+    pub highlight: bool,
+}
+
+pub(crate) fn search_query(
     schema: &Schema,
-    fields: &HashMap<String, String>,
+    params: &SearchParams,
     raw_query: Option<String>,
-) -> Result<Vec<Value>, CompassError> {
-    let converters: HashMap<String, ConverterSchema> = schema
-        .fields
-        .iter()
-        .filter_map(|(k, v)| {
-            v.converter.map(|converter| (k.to_owned(), converter))
-        })
-        .collect();
-
-    let (query, sort_string, json_query, other_bindings) =
-        generate_where(schema, fields, 5, raw_query.is_some())?;
+) -> Result<PreparedSearch, CompassError> {
+    let (query, sort_string, json_query, mut other_bindings) =
+        generate_where_params(schema, params, 4, raw_query.is_some())?;
 
     let json_query = if let Some(q) = raw_query {
         q
@@ -348,72 +113,196 @@ pub fn json_search(
         json_query
     };
 
+    let sort = Sort::from_params(schema, params)?;
+
+    let query = match params.options.get("cursor") {
+        Some(c) => {
+            let after = sort.after(&Cursor::decode(c)?, &mut other_bindings, 4)?;
+            if query.is_empty() {
+                format!("WHERE {}", after)
+            } else {
+                format!("{} AND {}", query, after)
+            }
+        }
+        None => query,
+    };
+
+    let highlight = match params.options.get("highlight") {
+        Some(h) => h.parse::<bool>().map_err(CompassError::InvalidBoolError)?,
+        None => false,
+    };
+
+    let headlines = if highlight {
+        format!(
+            ", {}",
+            headlines_expr(schema, &fulltext_matches(schema, params)?)?
+        )
+    } else {
+        String::new()
+    };
+
+    let object = match params.options.get("fields") {
+        Some(f) => projection_expr(schema, f)?,
+        None => "object".to_owned(),
+    };
+
     let query = format!(
-        "SELECT object FROM {} {} {}",
-        schema.table, query, sort_string
+        "SELECT {}, doc_id, {}{} FROM {} {} {}",
+        object,
+        sort.values(),
+        headlines,
+        escape::sql_identifier(&schema.table)?,
+        query,
+        sort_string
     );
 
-    let statement: Statement = client
-        .prepare_typed(query.as_str(), &[PostgresType::TEXT, PostgresType::TEXT])
-        .map_err(CompassError::PGError)?;
-
-    let sort_by = match fields.get("sortby") {
-        Some(l) => l.as_str(),
-        None => schema.default_order_by.as_str(),
+    let limit = params.limit.unwrap_or(100);
+    // a cursor already says where the page starts, so an offset left over from paging the old way would only skip rows
+    let offset = match params.options.get("cursor") {
+        Some(_) => 0,
+        None => params.offset.unwrap_or(0),
     };
 
-    let limit = match fields.get("limit") {
-        Some(l) => l.parse::<i64>().map_err(CompassError::InvalidNumberError)?,
-        None => 100,
+    let mut params: Vec<Box<dyn ToSql + Sync + Send>> =
+        vec![Box::new(json_query), Box::new(limit), Box::new(offset)];
+    params.extend(
+        other_bindings
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn ToSql + Sync + Send>),
+    );
+
+    Ok(PreparedSearch {
+        prepared: PreparedQuery {
+            query,
+            types: vec![PostgresType::TEXT],
+            params,
+        },
+        sort,
+        limit,
+        highlight,
+    })
+}
+
+/// Turns the rows of a search into a page, with a cursor pointing at the last row if there might be more after it.
+pub(crate) fn search_page(rows: Vec<Row>, schema: &Schema, search: &PreparedSearch) -> SearchPage {
+    let next_cursor = match rows.last() {
+        Some(last) if rows.len() as i64 >= search.limit => Some(
+            search
+                .sort
+                .cursor(last.get::<usize, Value>(2), last.get::<usize, Uuid>(1))
+                .encode(),
+        ),
+        _ => None,
     };
 
-    let offset = match fields.get("offset") {
-        Some(l) => l.parse::<i64>().map_err(CompassError::InvalidNumberError)?,
-        None => 0,
+    let headlines: Vec<Value> = if search.highlight {
+        rows.iter().map(|x| x.get::<usize, Value>(3)).collect()
+    } else {
+        Vec::new()
     };
 
-    let params: Vec<&dyn ToSql> = vec![&json_query, &sort_by, &limit, &offset];
+    let mut results = convert_rows(rows, schema);
+    for (result, headline) in results.iter_mut().zip(headlines) {
+        if let Some(obj) = result.as_object_mut() {
+            obj.insert(schema.highlight_key.to_owned(), headline);
+        }
+    }
 
-    let rows: Vec<Row> = client
-        .query_raw(
-            &statement,
-            params
-                .iter()
-                .copied()
-                .chain(other_bindings.iter().map(|x| &*x as &dyn ToSql))
-                .collect::<Vec<&dyn ToSql>>(),
-        )
-        .map_err(CompassError::PGError)?
-        .collect()
-        .map_err(CompassError::PGError)?;
+    SearchPage {
+        results,
+        next_cursor,
+    }
+}
 
-    Ok(rows
-        .into_iter()
+pub(crate) fn count_query(
+    schema: &Schema,
+    params: &SearchParams,
+) -> Result<PreparedQuery, CompassError> {
+    let (query, _, json_query, other_bindings) = generate_where_params(schema, params, 2, false)?;
+    let query = format!(
+        "SELECT COUNT(*) FROM {} {}",
+        escape::sql_identifier(&schema.table)?,
+        query
+    );
+
+    let mut params: Vec<Box<dyn ToSql + Sync + Send>> = vec![Box::new(json_query)];
+    params.extend(
+        other_bindings
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn ToSql + Sync + Send>),
+    );
+
+    Ok(PreparedQuery {
+        query,
+        types: vec![PostgresType::TEXT],
+        params,
+    })
+}
+
+pub(crate) fn by_ids_query(schema: &Schema) -> Result<String, CompassError> {
+    Ok(format!(
+        "SELECT object FROM {} WHERE doc_id = ANY($1)",
+        escape::sql_identifier(&schema.table)?
+    ))
+}
+
+/// Turns result rows into what clients get: converters reverted, and fields they can't see taken out.
+pub(crate) fn convert_rows(rows: Vec<Row>, schema: &Schema) -> Vec<Value> {
+    let converters = converters(schema);
+    rows.into_iter()
         .map(|x| {
             let mut val = x.get::<usize, Value>(0);
             for (key, conv) in converters.iter() {
-                if let Some(field) = val.get_mut(key) {
-                    match (conv.from, conv.to) {
-                        (ConvertFrom::DateTimeString, ConvertTo::Timestamp) => {
-                            // convert timestamps back into date-strings
-                            let timest = field.as_i64().unwrap();
-                            let dt = DateTime::<Utc>::from_utc(
-                                NaiveDateTime::from_timestamp(timest, 0),
-                                Utc,
-                            );
-                            *field = json!(dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
-                        }
-                        (ConvertFrom::DateTimeString, ConvertTo::TimestampMillis) => {
-                            let dt = Utc.timestamp_millis(field.as_i64().unwrap());
-                            *field = json!(dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
-                        }
-                        _ => {}
-                    }
+                // projected results have nulls for keys the document didn't have
+                if let Some(field) = val.get_mut(key).filter(|f| !f.is_null()) {
+                    conv.revert(field);
                 }
             }
+            strip_unreturnable(schema, &mut val);
             val
         })
-        .collect())
+        .collect()
+}
+
+/// One page of search results. Pass `next_cursor` back as the `cursor` parameter to get the next page.
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchPage {
+    pub results: Vec<Value>,
+    pub next_cursor: Option<String>,
+}
+
+pub fn json_search(
+    client: &mut Client,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+    raw_query: Option<String>,
+) -> Result<SearchPage, CompassError> {
+    json_search_params(
+        client,
+        schema,
+        &SearchParams::from_fields(fields)?,
+        raw_query,
+    )
+}
+
+/// Like [`json_search`], taking parameters parsed with [`SearchParams::parse`].
+pub fn json_search_params(
+    client: &mut Client,
+    schema: &Schema,
+    params: &SearchParams,
+    raw_query: Option<String>,
+) -> Result<SearchPage, CompassError> {
+    let search = search_query(schema, params, raw_query)?;
+
+    let statement: Statement = client
+        .prepare_typed(search.prepared.query.as_str(), &search.prepared.types)
+        .map_err(CompassError::from)?;
+
+    let rows: Vec<Row> = client
+        .query(&statement, &search.prepared.params())
+        .map_err(CompassError::from)?;
+
+    Ok(search_page(rows, schema, &search))
 }
 
 pub fn json_count(
@@ -421,28 +310,25 @@ pub fn json_count(
     schema: &Schema,
     fields: &HashMap<String, String>,
 ) -> Result<i64, CompassError> {
-    let (query, _, json_query, other_bindings) = generate_where(schema, fields, 2, false)?;
-    let query = format!("SELECT COUNT(*) FROM {} {}", schema.table, query);
+    json_count_params(client, schema, &SearchParams::from_fields(fields)?)
+}
 
-    let statement: Statement = client
-        .prepare_typed(query.as_str(), &[PostgresType::TEXT])
-        .map_err(CompassError::PGError)?;
+/// Like [`json_count`], taking parameters parsed with [`SearchParams::parse`].
+pub fn json_count_params(
+    client: &mut Client,
+    schema: &Schema,
+    params: &SearchParams,
+) -> Result<i64, CompassError> {
+    let prepared = count_query(schema, params)?;
 
-    let params: Vec<&dyn ToSql> = vec![&json_query];
+    let statement: Statement = client
+        .prepare_typed(prepared.query.as_str(), &prepared.types)
+        .map_err(CompassError::from)?;
 
     let res: Row = client
-        .query_raw(
-            &statement,
-            params
-                .iter()
-                .copied()
-                .chain(other_bindings.iter().map(|x| &*x as &dyn ToSql))
-                .collect::<Vec<&dyn ToSql>>(),
-        )
-        .map_err(CompassError::PGError)?
-        .next()?
-        .unwrap();
-    res.try_get::<usize, i64>(0).map_err(CompassError::PGError)
+        .query_one(&statement, &prepared.params())
+        .map_err(CompassError::from)?;
+    res.try_get::<usize, i64>(0).map_err(CompassError::from)
 }
 
 pub fn get_by_ids(
@@ -450,44 +336,6 @@ pub fn get_by_ids(
     schema: &Schema,
     ids: &Vec<Uuid>,
 ) -> Result<Vec<Value>, CompassError> {
-    // make a table of field -> converter, to see if we need to do any conversions on the results
-    let converters: HashMap<String, ConverterSchema> = schema
-        .fields
-        .iter()
-        .filter_map(|(k, v)| {
-            v.converter.map(|converter| (k.to_owned(), converter))
-        })
-        .collect();
-
-    Ok(client
-        .query(
-            format!("SELECT object FROM {} WHERE doc_id = ANY($1)", schema.table).as_str(),
-            &[ids],
-        )?
-        .into_iter()
-        .map(|x| {
-            let mut val = x.get::<usize, Value>(0);
-            for (key, conv) in converters.iter() {
-                if let Some(field) = val.get_mut(key) {
-                    match (conv.from, conv.to) {
-                        (ConvertFrom::DateTimeString, ConvertTo::Timestamp) => {
-                            // convert timestamps back into date-strings
-                            let timest = field.as_i64().unwrap();
-                            let dt = DateTime::<Utc>::from_utc(
-                                NaiveDateTime::from_timestamp(timest, 0),
-                                Utc,
-                            );
-                            *field = json!(dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
-                        }
-                        (ConvertFrom::DateTimeString, ConvertTo::TimestampMillis) => {
-                            let dt = Utc.timestamp_millis(field.as_i64().unwrap());
-                            *field = json!(dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            val
-        })
-        .collect())
+    let rows = client.query(by_ids_query(schema)?.as_str(), &[ids])?;
+    Ok(convert_rows(rows, schema))
 }
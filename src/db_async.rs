@@ -0,0 +1,139 @@
+use super::*;
+
+use db::{by_ids_query, convert_rows, count_query, search_page, search_query};
+use facets::{facet_buckets, facet_query};
+use histogram::{histogram_buckets, histogram_query};
+
+use tokio_postgres::{GenericClient, Row, Statement};
+
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+pub async fn json_search_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+    raw_query: Option<String>,
+) -> Result<SearchPage, CompassError> {
+    json_search_params_async(
+        client,
+        schema,
+        &SearchParams::from_fields(fields)?,
+        raw_query,
+    )
+    .await
+}
+
+pub async fn json_search_params_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    params: &SearchParams,
+    raw_query: Option<String>,
+) -> Result<SearchPage, CompassError> {
+    let search = search_query(schema, params, raw_query)?;
+
+    let statement: Statement = client
+        .prepare_typed(search.prepared.query.as_str(), &search.prepared.types)
+        .await
+        .map_err(CompassError::from)?;
+
+    let rows: Vec<Row> = client
+        .query(&statement, &search.prepared.params())
+        .await
+        .map_err(CompassError::from)?;
+
+    Ok(search_page(rows, schema, &search))
+}
+
+pub async fn json_count_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+) -> Result<i64, CompassError> {
+    json_count_params_async(client, schema, &SearchParams::from_fields(fields)?).await
+}
+
+pub async fn json_count_params_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    params: &SearchParams,
+) -> Result<i64, CompassError> {
+    let prepared = count_query(schema, params)?;
+
+    let statement: Statement = client
+        .prepare_typed(prepared.query.as_str(), &prepared.types)
+        .await
+        .map_err(CompassError::from)?;
+
+    let res: Row = client
+        .query_one(&statement, &prepared.params())
+        .await
+        .map_err(CompassError::from)?;
+    res.try_get::<usize, i64>(0).map_err(CompassError::from)
+}
+
+pub async fn json_facets_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+    facets: &[String],
+    options: &FacetOptions,
+) -> Result<Vec<Facet>, CompassError> {
+    let params = SearchParams::from_fields(fields)?;
+    let mut res = Vec::new();
+
+    for facet in facets {
+        let prepared = facet_query(schema, &params, facet, options)?;
+
+        let statement: Statement = client
+            .prepare_typed(prepared.query.as_str(), &prepared.types)
+            .await
+            .map_err(CompassError::from)?;
+
+        let rows: Vec<Row> = client
+            .query(&statement, &prepared.params())
+            .await
+            .map_err(CompassError::from)?;
+
+        res.push(Facet {
+            field: facet.to_owned(),
+            buckets: facet_buckets(rows),
+        });
+    }
+
+    Ok(res)
+}
+
+pub async fn json_histogram_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+    field: &str,
+    buckets: HistogramBuckets,
+) -> Result<Vec<HistogramBucket>, CompassError> {
+    let prepared = histogram_query(schema, &SearchParams::from_fields(fields)?, field, buckets)?;
+
+    let statement: Statement = client
+        .prepare_typed(prepared.query.as_str(), &prepared.types)
+        .await
+        .map_err(CompassError::from)?;
+
+    let rows: Vec<Row> = client
+        .query(&statement, &prepared.params())
+        .await
+        .map_err(CompassError::from)?;
+
+    Ok(histogram_buckets(rows))
+}
+
+pub async fn get_by_ids_async<C: GenericClient>(
+    client: &C,
+    schema: &Schema,
+    ids: &Vec<Uuid>,
+) -> Result<Vec<Value>, CompassError> {
+    let rows = client.query(by_ids_query(schema)?.as_str(), &[ids]).await?;
+    Ok(convert_rows(rows, schema))
+}
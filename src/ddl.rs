@@ -0,0 +1,170 @@
+use super::*;
+
+/// Generates `CREATE TABLE` and `CREATE INDEX` statements for a schema. Index expressions are built with the same functions queries are, so they always line up.
+pub fn generate_ddl(schema: &Schema) -> Result<String, CompassError> {
+    let table = escape::sql_identifier(&schema.table)?;
+    // indexes always live in their table's namespace, so they can't be qualified
+    let table_name = table.rsplit('.').next().unwrap();
+    let index_name =
+        |suffix: &str| escape::sql_quoted_identifier(&format!("{}_{}", table_name, suffix));
+
+    let mut statements = vec![
+        format!(
+            "-- create main table\nCREATE TABLE {} (\n    doc_id uuid PRIMARY KEY,\n    object jsonb\n);",
+            table
+        ),
+        format!(
+            "-- creates index on documents for faster JSONPATH matching\nCREATE INDEX {} ON {} USING gin (object jsonb_path_ops);",
+            index_name("object_idx"),
+            table
+        ),
+    ];
+
+    // sorted so the output is stable
+    let mut names: Vec<&String> = schema.fields.keys().collect();
+    names.sort();
+
+    let mut sort_keys: Vec<SortKey> = Vec::new();
+
+    for name in names {
+        let field = &schema.fields[name];
+
+        if let Some((lang, targets)) = fulltext_of(name, &field.query) {
+            statements.push(format!(
+                "-- full-text search on '{}'\nCREATE INDEX {} ON {} USING gin ({});",
+                name,
+                index_name(&format!("{}_fts_idx", name)),
+                table,
+                fulltext_vector(lang, &targets)?
+            ));
+        }
+
+        if let FieldQuery::Range { .. } = field.query {
+            sort_keys.push(SortKey::parse(schema, name, true, None)?);
+        }
+    }
+
+    for key in Sort::parse(schema, &schema.default_order_by, true, None)?.keys {
+        if !sort_keys.iter().any(|k| k.expr() == key.expr()) {
+            sort_keys.push(key);
+        }
+    }
+
+    for key in sort_keys {
+        statements.push(format!(
+            "-- sorting by '{}'\nCREATE INDEX {} ON {} ({});",
+            key.path,
+            index_name(&format!("{}_sort_idx", key.path.0.join("_"))),
+            table,
+            key.expr()
+        ));
+    }
+
+    Ok(statements.join("\n\n"))
+}
+
+fn fulltext_of<'a>(name: &str, query: &'a FieldQuery) -> Option<(&'a str, Vec<FulltextTarget>)> {
+    match query {
+        FieldQuery::Fulltext {
+            ref lang,
+            ref target,
+            ref targets,
+            ..
+        } => Some((lang, fulltext_targets(name, target.as_deref(), targets))),
+        FieldQuery::Not(inner) => fulltext_of(name, inner),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = "table: public.docs\ndefault_order_by: season,-date,name\nfields:\n  season: {name: season, query: {type: Range, min: season_min, max: season_max}}\n  date: {name: date, converter: {from: DateTimeString, to: Timestamp}, query: {type: Range, min: after, max: before}}\n  we\"ird: {name: we\"ird, query: {type: Range, min: w_min, max: w_max}}\n  name: {name: name, query: {type: StringTag}}\n  search: {name: search, query: {type: Fulltext, lang: english, targets: [{field: title, weight: A}, {field: body}]}}\n";
+
+    fn schema() -> Schema {
+        Schema::from_yaml_str(SCHEMA).unwrap()
+    }
+
+    fn index_on(ddl: &str, name: &str) -> String {
+        let prefix = format!("CREATE INDEX {} ON public.docs ", name);
+        let line = ddl
+            .lines()
+            .find(|l| l.starts_with(&prefix))
+            .unwrap_or_else(|| panic!("no index {}", name));
+        line[prefix.len()..].to_owned()
+    }
+
+    #[test]
+    fn fulltext_indexes_match_searches() {
+        let schema = schema();
+        let ddl = generate_ddl(&schema).unwrap();
+
+        let targets = vec![
+            FulltextTarget {
+                field: "title".to_owned(),
+                weight: Some(FulltextWeight::A),
+            },
+            FulltextTarget {
+                field: "body".to_owned(),
+                weight: None,
+            },
+        ];
+        let vector = fulltext_vector("english", &targets).unwrap();
+        assert_eq!(
+            index_on(&ddl, "\"docs_search_fts_idx\""),
+            format!("USING gin ({});", vector)
+        );
+
+        let mut bindings = Vec::new();
+        let search = Query::from_params(&schema, &SearchParams::parse("search=cat").unwrap())
+            .unwrap()
+            .to_sql(&mut bindings, 1)
+            .unwrap();
+        assert!(search.contains(&format!("{} @@ ", vector)));
+    }
+
+    #[test]
+    fn sort_indexes_match_sorting() {
+        let schema = schema();
+        let ddl = generate_ddl(&schema).unwrap();
+
+        for (key, index) in [
+            ("season", "\"docs_season_sort_idx\""),
+            ("date", "\"docs_date_sort_idx\""),
+            ("name", "\"docs_name_sort_idx\""),
+            ("we\"ird", "\"docs_we\"\"ird_sort_idx\""),
+        ] {
+            let expr = SortKey::parse(&schema, key, true, None).unwrap();
+            assert_eq!(index_on(&ddl, index), format!("({});", expr.expr()));
+        }
+
+        // timestamps sort as integers, not as any old number
+        assert!(index_on(&ddl, "\"docs_date_sort_idx\"").contains("::bigint"));
+    }
+
+    #[test]
+    fn default_order_by_keys_are_deduplicated() {
+        let ddl = generate_ddl(&schema()).unwrap();
+
+        // season and date are ranges and in default_order_by too, name is only in the latter
+        for name in ["season", "date", "name"] {
+            let count = ddl
+                .lines()
+                .filter(|l| l.starts_with(&format!("CREATE INDEX \"docs_{}_sort_idx\"", name)))
+                .count();
+            assert_eq!(count, 1, "{} is indexed {} times", name, count);
+        }
+        assert_eq!(ddl.matches("CREATE INDEX").count(), 6);
+    }
+
+    #[test]
+    fn table_names_are_checked() {
+        let mut schema = schema();
+        schema.table = "docs; DROP TABLE docs".to_owned();
+        assert!(matches!(
+            generate_ddl(&schema),
+            Err(CompassError::InvalidIdentifier(_))
+        ));
+    }
+}
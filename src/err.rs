@@ -1,6 +1,10 @@
+use super::SchemaProblem;
 use postgres::error::Error as PGError;
+use postgres::error::SqlState;
 use serde_json::error::Error as SerdeError;
+use serde_yaml::Error as YAMLError;
 use std::fmt;
+use std::io::Error as IOError;
 use std::num::ParseIntError;
 use std::str::ParseBoolError;
 
@@ -10,14 +14,30 @@ pub enum CompassError {
     PGError(PGError),
     JSONError(SerdeError),
     InvalidNumberError(ParseIntError),
+    InvalidDecimalError(String),
     InvalidBoolError(ParseBoolError),
+    InvalidIdentifier(String),
+    YAMLError(YAMLError),
+    IOError(IOError),
+    InvalidSchema(Vec<SchemaProblem>),
+    ConversionError(String),
+    InvalidCursor,
+    InvalidFacet(String),
+    InvalidHistogram(String),
+    InvalidExpression(String),
 }
 
 impl std::error::Error for CompassError {}
 
+// a bad user regex only shows up once postgres runs it, and that's the client's mistake rather than ours
 impl From<PGError> for CompassError {
     fn from(err: PGError) -> CompassError {
-        CompassError::PGError(err)
+        match err.as_db_error() {
+            Some(e) if e.code() == &SqlState::INVALID_REGULAR_EXPRESSION => {
+                CompassError::InvalidExpression(e.message().to_owned())
+            }
+            _ => CompassError::PGError(err),
+        }
     }
 }
 
@@ -27,6 +47,18 @@ impl From<SerdeError> for CompassError {
     }
 }
 
+impl From<YAMLError> for CompassError {
+    fn from(err: YAMLError) -> CompassError {
+        CompassError::YAMLError(err)
+    }
+}
+
+impl From<IOError> for CompassError {
+    fn from(err: IOError) -> CompassError {
+        CompassError::IOError(err)
+    }
+}
+
 impl From<ParseIntError> for CompassError {
     fn from(err: ParseIntError) -> CompassError {
         CompassError::InvalidNumberError(err)
@@ -73,6 +105,41 @@ impl<'r> Responder<'r, 'static> for CompassError {
                     .sized_body(r_text.len(), Cursor::new(r_text))
                     .ok()
             }
+            InvalidDecimalError(ref value) => {
+                let r_text = format!("couldn't parse decimal parameter '{}'", value);
+                Response::build()
+                    .status(Status::BadRequest)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            InvalidCursor => {
+                let r_text = "invalid or expired cursor";
+                Response::build()
+                    .status(Status::BadRequest)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            InvalidFacet(ref field) => {
+                let r_text = format!("can't facet on field '{}'", field);
+                Response::build()
+                    .status(Status::BadRequest)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            InvalidHistogram(ref field) => {
+                let r_text = format!("can't build that histogram on field '{}'", field);
+                Response::build()
+                    .status(Status::BadRequest)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            InvalidExpression(ref reason) => {
+                let r_text = format!("invalid expression: {}", reason);
+                Response::build()
+                    .status(Status::BadRequest)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
             InvalidBoolError(_) => {
                 let r_text = "couldn't parse boolean parameter";
                 Response::build()
@@ -80,6 +147,13 @@ impl<'r> Responder<'r, 'static> for CompassError {
                     .sized_body(r_text.len(), Cursor::new(r_text))
                     .ok()
             }
+            InvalidIdentifier(_) => {
+                let r_text = "schema contains an invalid identifier";
+                Response::build()
+                    .status(Status::InternalServerError)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
             PGError(ref err) => {
                 let r_text = err.to_string();
                 Response::build()
@@ -87,6 +161,38 @@ impl<'r> Responder<'r, 'static> for CompassError {
                     .sized_body(r_text.len(), Cursor::new(r_text))
                     .ok()
             }
+            YAMLError(ref err) => {
+                let r_text = err.to_string();
+                Response::build()
+                    .status(Status::InternalServerError)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            IOError(ref err) => {
+                let r_text = err.to_string();
+                Response::build()
+                    .status(Status::InternalServerError)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            ConversionError(ref reason) => {
+                let r_text = reason.to_owned();
+                Response::build()
+                    .status(Status::BadRequest)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
+            InvalidSchema(ref problems) => {
+                let r_text = problems
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                Response::build()
+                    .status(Status::InternalServerError)
+                    .sized_body(r_text.len(), Cursor::new(r_text))
+                    .ok()
+            }
             JSONError(ref err) => {
                 let r_text = err.to_string();
                 Response::build()
@@ -0,0 +1,269 @@
+use super::*;
+
+// JSONPath string literals share JSON's escaping rules, so serde_json can quote them for us.
+// we escape instead of passing values through jsonb_path_match's vars so that searches keep using the `@@` operator, and with it the GIN index.
+pub fn jsonpath_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+/// Escapes everything a regular expression would treat specially, so `s` matches only itself.
+pub fn regex_literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// lowercase Latin letters, and the accented letters that should match them
+const ACCENTED: &[(char, &str)] = &[
+    ('a', "àáâãäåāăą"),
+    ('c', "çćĉċč"),
+    ('d', "ďđ"),
+    ('e', "èéêëēĕėęě"),
+    ('g', "ĝğġģ"),
+    ('h', "ĥħ"),
+    ('i', "ìíîïĩīĭįı"),
+    ('j', "ĵ"),
+    ('k', "ķ"),
+    ('l', "ĺļľŀł"),
+    ('n', "ñńņň"),
+    ('o', "òóôõöøōŏő"),
+    ('r', "ŕŗř"),
+    ('s', "śŝşš"),
+    ('t', "ţťŧ"),
+    ('u', "ùúûüũūŭůűų"),
+    ('w', "ŵ"),
+    ('y', "ýÿŷ"),
+    ('z', "źżž"),
+];
+
+/// Like [`regex_literal`], except letters also match their accented forms, whichever form `s` uses: `Jose` and `José` both turn into `Jos[eèéêëēĕėęě]`.
+pub fn regex_literal_ignoring_accents(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        let group = ACCENTED
+            .iter()
+            .find(|(base, accented)| *base == lower || accented.contains(lower));
+
+        match group {
+            Some((base, accented)) => {
+                let class = format!("{}{}", base, accented);
+                out.push('[');
+                if c.is_uppercase() {
+                    out.extend(class.chars().flat_map(char::to_uppercase));
+                } else {
+                    out.push_str(&class);
+                }
+                out.push(']');
+            }
+            None => out.push_str(&regex_literal(&c.to_string())),
+        }
+    }
+    out
+}
+
+// the E'' form reads backslashes as escapes no matter how standard_conforming_strings is set
+pub fn sql_literal(s: &str) -> String {
+    if s.contains('\\') {
+        format!("E'{}'", s.replace('\\', "\\\\").replace('\'', "''"))
+    } else {
+        format!("'{}'", s.replace('\'', "''"))
+    }
+}
+
+/// Quotes a list of strings as a `text[]` literal, e.g. `'{"a","b"}'`.
+pub fn sql_text_array(items: &[String]) -> String {
+    let elements: Vec<String> = items
+        .iter()
+        .map(|x| format!("\"{}\"", x.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    sql_literal(&format!("{{{}}}", elements.join(",")))
+}
+
+/// Quotes a name as a SQL identifier, for names we make up ourselves (like index names).
+pub fn sql_quoted_identifier(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Checks that a name from the schema (a table, a text search configuration) is a plain, optionally schema-qualified, SQL identifier.
+pub fn sql_identifier(s: &str) -> Result<&str, CompassError> {
+    let mut parts = s.split('.');
+    let valid = match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), None, None) => is_identifier(name),
+        (Some(namespace), Some(name), None) => is_identifier(namespace) && is_identifier(name),
+        _ => false,
+    };
+
+    if valid {
+        Ok(s)
+    } else {
+        Err(CompassError::InvalidIdentifier(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jsonpath_strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(jsonpath_string("plain"), r#""plain""#);
+        assert_eq!(jsonpath_string(r#"a"b"#), r#""a\"b""#);
+        assert_eq!(jsonpath_string(r"a\b"), r#""a\\b""#);
+        assert_eq!(jsonpath_string("a\nb\tc\u{1}"), r#""a\nb\tc\u0001""#);
+        assert_eq!(
+            jsonpath_string(r#"" || $.x == "y"#),
+            r#""\" || $.x == \"y""#
+        );
+    }
+
+    #[test]
+    fn sql_literals_escape_quotes_and_backslashes() {
+        assert_eq!(sql_literal("english"), "'english'");
+        assert_eq!(sql_literal("it's"), "'it''s'");
+        assert_eq!(sql_literal("'; DROP TABLE x; --"), "'''; DROP TABLE x; --'");
+        // would close the literal early with standard_conforming_strings off, if it weren't for E''
+        assert_eq!(sql_literal(r"a\'"), r"E'a\\'''");
+        assert_eq!(sql_literal(r"\"), r"E'\\'");
+    }
+
+    #[test]
+    fn sql_text_arrays_escape_elements_and_the_literal() {
+        assert_eq!(
+            sql_text_array(&["a".to_owned(), "b".to_owned()]),
+            r#"'{"a","b"}'"#
+        );
+        assert_eq!(
+            sql_text_array(&[r#"a"b"#.to_owned(), "c'd".to_owned()]),
+            r#"E'{"a\\"b","c''d"}'"#
+        );
+        assert_eq!(
+            sql_text_array(&[r"e\f".to_owned(), "}".to_owned()]),
+            r#"E'{"e\\\\f","}"}'"#
+        );
+    }
+
+    #[test]
+    fn sql_identifiers_accept_plain_and_qualified_names() {
+        assert_eq!(sql_identifier("documents").unwrap(), "documents");
+        assert_eq!(
+            sql_identifier("public.documents").unwrap(),
+            "public.documents"
+        );
+        assert_eq!(sql_identifier("_t2").unwrap(), "_t2");
+    }
+
+    #[test]
+    fn sql_identifiers_reject_anything_else() {
+        for name in [
+            "",
+            "documents; DROP TABLE documents",
+            "english'",
+            "\"documents\"",
+            "a.b.c",
+            ".documents",
+            "documents.",
+            "2documents",
+            "docu ments",
+            "documents--",
+            "dócuments",
+        ] {
+            assert!(
+                matches!(sql_identifier(name), Err(CompassError::InvalidIdentifier(n)) if n == name),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn quoted_identifiers_double_quotes() {
+        assert_eq!(sql_quoted_identifier("t_idx"), "\"t_idx\"");
+        assert_eq!(sql_quoted_identifier("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn regex_literals_escape_every_metacharacter() {
+        assert_eq!(regex_literal("plain text"), "plain text");
+        assert_eq!(
+            regex_literal(r"\.^$|?*+()[]{}"),
+            r"\\\.\^\$\|\?\*\+\(\)\[\]\{\}"
+        );
+        assert_eq!(regex_literal("a.b"), r"a\.b");
+        // quotes are left to the JSONPath string escaping
+        assert_eq!(regex_literal(r#"a"b"#), r#"a"b"#);
+    }
+
+    #[test]
+    fn accent_classes_keep_case() {
+        assert_eq!(
+            regex_literal_ignoring_accents("José"),
+            "[JĴ][oòóôõöøōŏő][sśŝşš][eèéêëēĕėęě]"
+        );
+        assert_eq!(
+            regex_literal_ignoring_accents("ÉLAN"),
+            "[EÈÉÊËĒĔĖĘĚ][LĹĻĽĿŁ][AÀÁÂÃÄÅĀĂĄ][NÑŃŅŇ]"
+        );
+        assert_eq!(regex_literal_ignoring_accents("a.1"), r"[aàáâãäåāăą]\.1");
+        assert_eq!(regex_literal_ignoring_accents("(?)"), r"\(\?\)");
+    }
+
+    fn pattern_filter(query: &str) -> String {
+        let schema = Schema::from_yaml_str(
+            "table: t\ndefault_order_by: a\nfields:\n  a: {name: a, query: {type: Pattern, mode: Prefix}}\n  ai: {name: ai, query: {type: Pattern, mode: Prefix, case_insensitive: true}}\n  sub: {name: sub, query: {type: Pattern, mode: Substring, case_insensitive: true}}\n  re: {name: re, query: {type: Pattern, mode: Regex}}\n  tag: {name: tag, query: {type: StringTag, case_insensitive: true}}\n  acc: {name: acc, query: {type: StringTag, accent_insensitive: true}}\n",
+        )
+        .unwrap();
+        Query::from_params(&schema, &SearchParams::parse(query).unwrap())
+            .unwrap()
+            .to_jsonpath()
+            .unwrap()
+    }
+
+    #[test]
+    fn pattern_fields_render_escaped_regexes() {
+        for (query, filter) in [
+            // a case-sensitive prefix doesn't need a regex at all
+            ("a=Jo.s", r#"(($."a" starts with "Jo.s"))"#),
+            ("ai=Jo.s", r#"(($."ai" like_regex "^Jo\\.s" flag "i"))"#),
+            (
+                "sub=a%2Bb(c)",
+                r#"(($."sub" like_regex "a\\+b\\(c\\)" flag "i"))"#,
+            ),
+            // regexes are the client's own, but still can't break out of the string
+            ("re=^a.*\"$", r#"(($."re" like_regex "^a.*\"$"))"#),
+            (
+                r#"re=" || @ == "x"#,
+                r#"(($."re" like_regex "\" || @ == \"x"))"#,
+            ),
+        ] {
+            assert_eq!(pattern_filter(query), filter, "for {:?}", query);
+        }
+    }
+
+    #[test]
+    fn loose_string_tags_render_anchored_regexes() {
+        assert_eq!(
+            pattern_filter("tag=A|B"),
+            r#"(($."tag" like_regex "^A\\|B$" flag "i"))"#
+        );
+        assert_eq!(
+            pattern_filter("acc=ÉLAN"),
+            r#"(($."acc" like_regex "^[EÈÉÊËĒĔĖĘĚ][LĹĻĽĿŁ][AÀÁÂÃÄÅĀĂĄ][NÑŃŅŇ]$"))"#
+        );
+    }
+}
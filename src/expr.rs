@@ -0,0 +1,337 @@
+use super::*;
+
+use parse::collapse;
+
+// q=(team:X OR opponent:X) AND season>=16 AND NOT tag:foo
+//
+// expr := and ("OR" and)*
+// and  := not ("AND" not)*
+// not  := "NOT" not | "(" expr ")" | term
+// term := key (":" | "=" | ">" | ">=" | "<" | "<=") value
+//
+// `key:value` means exactly what the URL parameter `key=value` does. values can be "quoted", with \ escaping
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term {
+        key: String,
+        op: String,
+        value: String,
+    },
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Open => "'('".to_owned(),
+            Token::Close => "')'".to_owned(),
+            Token::And => "AND".to_owned(),
+            Token::Or => "OR".to_owned(),
+            Token::Not => "NOT".to_owned(),
+            Token::Term { key, op, value } => format!("'{}{}{}'", key, op, value),
+        }
+    }
+}
+
+/// How deeply parentheses and `NOT`s can nest. Plenty for a real query, and little enough that a hostile one can't run the parser out of stack.
+pub(crate) const MAX_NESTING: usize = 64;
+
+fn invalid(reason: String) -> CompassError {
+    CompassError::InvalidExpression(reason)
+}
+
+fn is_op(c: char) -> bool {
+    matches!(c, ':' | '=' | '<' | '>')
+}
+
+fn tokenize(q: &str) -> Result<Vec<Token>, CompassError> {
+    let chars: Vec<char> = q.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+            continue;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < chars.len()
+            && !chars[i].is_whitespace()
+            && !"()".contains(chars[i])
+            && !is_op(chars[i])
+        {
+            i += 1;
+        }
+        let key: String = chars[start..i].iter().collect();
+
+        if i >= chars.len() || !is_op(chars[i]) {
+            tokens.push(match key.as_str() {
+                "AND" => Token::And,
+                "OR" => Token::Or,
+                "NOT" => Token::Not,
+                _ => return Err(invalid(format!("expected an operator after '{}'", key))),
+            });
+            continue;
+        }
+
+        let op = if i + 1 < chars.len() && chars[i + 1] == '=' && "<>".contains(chars[i]) {
+            i += 2;
+            chars[i - 2..i].iter().collect()
+        } else {
+            i += 1;
+            chars[i - 1].to_string()
+        };
+
+        let mut value = String::new();
+        if i < chars.len() && chars[i] == '"' {
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('"') => break,
+                    Some('\\') if i + 1 < chars.len() => {
+                        value.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(c) => {
+                        value.push(*c);
+                        i += 1;
+                    }
+                    None => {
+                        return Err(invalid(format!("unterminated quote after '{}{}'", key, op)))
+                    }
+                }
+            }
+            i += 1;
+        } else {
+            while i < chars.len() && !chars[i].is_whitespace() && chars[i] != ')' {
+                value.push(chars[i]);
+                i += 1;
+            }
+        }
+
+        if key.is_empty() || value.is_empty() {
+            return Err(invalid(format!(
+                "incomplete filter '{}{}{}'",
+                key, op, value
+            )));
+        }
+
+        tokens.push(Token::Term { key, op, value });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    schema: &'a Schema,
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Query, CompassError> {
+        let mut alternatives = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            alternatives.push(self.and()?);
+        }
+        Ok(collapse(alternatives, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query, CompassError> {
+        let mut conjunction = vec![self.not()?];
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            conjunction.push(self.not()?);
+        }
+        Ok(collapse(conjunction, Query::And))
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Query, CompassError>
+    where
+        F: FnOnce(&mut Self) -> Result<Query, CompassError>,
+    {
+        if self.depth >= MAX_NESTING {
+            return Err(invalid(format!(
+                "nested more than {} levels deep",
+                MAX_NESTING
+            )));
+        }
+
+        self.depth += 1;
+        let q = parse(self);
+        self.depth -= 1;
+        q
+    }
+
+    fn not(&mut self) -> Result<Query, CompassError> {
+        match self.next() {
+            Some(Token::Not) => Ok(Query::Not(Box::new(self.nested(Self::not)?))),
+            Some(Token::Open) => {
+                let q = self.nested(Self::expr)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(q),
+                    _ => Err(invalid("missing ')'".to_owned())),
+                }
+            }
+            Some(Token::Term { key, op, value }) => self.term(&key, &op, &value),
+            Some(t) => Err(invalid(format!("unexpected {}", t.describe()))),
+            None => Err(invalid("unexpected end of expression".to_owned())),
+        }
+    }
+
+    // comparisons are the same as the min/max parameters, just with the bound's inclusivity spelled out
+    fn term(&self, key: &str, op: &str, value: &str) -> Result<Query, CompassError> {
+        let (name, query) = resolve_field(self.schema, key)
+            .ok_or_else(|| invalid(format!("unknown field '{}'", key)))?;
+
+        let number = match query {
+            FieldQuery::Range { number, .. } | FieldQuery::NumericTag { number, .. } => {
+                Some(number)
+            }
+            _ => None,
+        };
+
+        let query = match (op, number) {
+            (":", _) | ("=", _) => query,
+            (">", Some(number)) => FieldQuery::Min {
+                number,
+                inclusive: false,
+            },
+            (">=", Some(number)) => FieldQuery::Min {
+                number,
+                inclusive: true,
+            },
+            ("<", Some(number)) => FieldQuery::Max {
+                number,
+                inclusive: false,
+            },
+            ("<=", Some(number)) => FieldQuery::Max {
+                number,
+                inclusive: true,
+            },
+            _ => return Err(invalid(format!("'{}' can't be compared with {}", key, op))),
+        };
+
+        generate_one_field(self.schema, value, (&name, query))
+    }
+}
+
+impl Query {
+    /// Parses a `q=` expression, like `(team:X OR opponent:X) AND season>=16 AND NOT tag:foo`, checking every filter against the schema.
+    pub fn parse_expression(schema: &Schema, q: &str) -> Result<Query, CompassError> {
+        let mut parser = Parser {
+            schema,
+            tokens: tokenize(q)?,
+            pos: 0,
+            depth: 0,
+        };
+
+        let query = parser.expr()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(t) => Err(invalid(format!("unexpected {}", t.describe()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::from_yaml_str(
+            "table: t\ndefault_order_by: season\nfields:\n  season: {name: season, query: {type: Range, min: season_min, max: season_max}}\n  team: {name: team, query: {type: StringTag}}\n  name: {name: name, query: {type: StringTag}}\n",
+        )
+        .unwrap()
+    }
+
+    fn parse(q: &str) -> Result<String, String> {
+        match Query::parse_expression(&schema(), q) {
+            Ok(query) => Ok(query.to_jsonpath().unwrap()),
+            Err(CompassError::InvalidExpression(reason)) => Err(reason),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("team:a OR team:b AND season>=16").unwrap(),
+            r#"(($."team" == "a") || (($."team" == "b") && ($."season" >= 16)))"#
+        );
+        assert_eq!(
+            parse("(team:a OR team:b) AND NOT season<3").unwrap(),
+            r#"((($."team" == "a") || ($."team" == "b")) && !(($."season" < 3)))"#
+        );
+    }
+
+    #[test]
+    fn quoted_values() {
+        assert_eq!(
+            parse(r#"name:"a b \" c" OR name="AND""#).unwrap(),
+            r#"(($."name" == "a b \" c") || ($."name" == "AND"))"#
+        );
+        assert_eq!(
+            parse(r#"name:"x"#).unwrap_err(),
+            "unterminated quote after 'name:'"
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        for (q, reason) in [
+            ("team:a AND", "unexpected end of expression"),
+            ("NOT", "unexpected end of expression"),
+            ("team", "expected an operator after 'team'"),
+            ("(team:a", "missing ')'"),
+            ("team:a)", "unexpected ')'"),
+            ("team:a team:b", "unexpected 'team:b'"),
+            ("team:", "incomplete filter 'team:'"),
+            ("nope:x", "unknown field 'nope'"),
+            ("team>3", "'team' can't be compared with >"),
+        ] {
+            assert_eq!(parse(q).unwrap_err(), reason, "for {:?}", q);
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |n: usize| format!("{}team:x{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+        assert_eq!(
+            parse(&nested(MAX_NESTING + 1)).unwrap_err(),
+            "nested more than 64 levels deep"
+        );
+        assert!(parse(&format!("{}team:x", "(".repeat(3000))).is_err());
+
+        assert!(parse(&format!("{}team:x", "NOT ".repeat(MAX_NESTING))).is_ok());
+        assert!(parse(&format!("{}team:x", "NOT ".repeat(3000))).is_err());
+    }
+}
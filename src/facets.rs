@@ -0,0 +1,228 @@
+use super::*;
+
+use db::PreparedQuery;
+
+use postgres::types::ToSql;
+use postgres::types::Type as PostgresType;
+use postgres::{Client, Row, Statement};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FacetOptions {
+    /// how many buckets to return per field, most common values first
+    pub limit: i64,
+    /// count as if the facet's own field wasn't filtered on, so a multi-select UI can show the other options' counts
+    pub exclude_own_filter: bool,
+}
+
+impl Default for FacetOptions {
+    fn default() -> Self {
+        FacetOptions {
+            limit: 10,
+            exclude_own_filter: false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FacetBucket {
+    pub value: Value,
+    pub count: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Facet {
+    pub field: String,
+    pub buckets: Vec<FacetBucket>,
+}
+
+fn is_facetable(query: &FieldQuery) -> bool {
+    matches!(
+        query,
+        FieldQuery::StringTag { .. }
+            | FieldQuery::NumericTag { .. }
+            | FieldQuery::Bool
+            | FieldQuery::AmbiguousTag
+            | FieldQuery::Array { .. }
+    )
+}
+
+pub(crate) fn facet_query(
+    schema: &Schema,
+    params: &SearchParams,
+    facet: &str,
+    options: &FacetOptions,
+) -> Result<PreparedQuery, CompassError> {
+    match schema.fields.get(facet) {
+        Some(field) if !schema.returnable(field) => return Err(CompassError::FieldNotFound),
+        Some(field) if is_facetable(&field.query) => {}
+        Some(_) => return Err(CompassError::InvalidFacet(facet.to_owned())),
+        None => return Err(CompassError::FieldNotFound),
+    }
+
+    let filtered: SearchParams;
+    let params = if options.exclude_own_filter {
+        let mut p = params.clone();
+        p.filters
+            .retain(|(k, _)| !matches!(resolve_field(schema, k), Some((name, _)) if name == facet));
+        filtered = p;
+        &filtered
+    } else {
+        params
+    };
+
+    let (query, _, json_query, other_bindings) = generate_where_params(schema, params, 4, false)?;
+
+    // [*] unwraps arrays so tag arrays are counted per tag; on anything else it just yields the value
+    let query = format!(
+        "SELECT value, COUNT(DISTINCT doc_id) FROM {} CROSS JOIN LATERAL jsonb_path_query(object, CAST($2::text AS JSONPATH)) AS value {} GROUP BY value ORDER BY COUNT(DISTINCT doc_id) DESC, value LIMIT $3",
+        escape::sql_identifier(&schema.table)?,
+        query
+    );
+
+    let mut params: Vec<Box<dyn ToSql + Sync + Send>> = vec![
+        Box::new(json_query),
+        Box::new(format!("{}[*]", FieldPath::new(facet).to_jsonpath())),
+        Box::new(options.limit),
+    ];
+    params.extend(
+        other_bindings
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn ToSql + Sync + Send>),
+    );
+
+    Ok(PreparedQuery {
+        query,
+        types: vec![PostgresType::TEXT],
+        params,
+    })
+}
+
+pub(crate) fn facet_buckets(rows: Vec<Row>) -> Vec<FacetBucket> {
+    rows.into_iter()
+        .map(|x| FacetBucket {
+            value: x.get::<usize, Value>(0),
+            count: x.get::<usize, i64>(1),
+        })
+        .collect()
+}
+
+/// Counts how many matching documents have each value of the `facets` fields, filtered the same way `json_search` would be.
+pub fn json_facets(
+    client: &mut Client,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+    facets: &[String],
+    options: &FacetOptions,
+) -> Result<Vec<Facet>, CompassError> {
+    let params = SearchParams::from_fields(fields)?;
+    let mut res = Vec::new();
+
+    for facet in facets {
+        let prepared = facet_query(schema, &params, facet, options)?;
+
+        let statement: Statement = client
+            .prepare_typed(prepared.query.as_str(), &prepared.types)
+            .map_err(CompassError::from)?;
+
+        let rows: Vec<Row> = client
+            .query(&statement, &prepared.params())
+            .map_err(CompassError::from)?;
+
+        res.push(Facet {
+            field: facet.to_owned(),
+            buckets: facet_buckets(rows),
+        });
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str =
+        "to_tsvector('english',object->>'text') @@ websearch_to_tsquery('english',$4)";
+
+    fn schema() -> Schema {
+        Schema::from_yaml_str(
+            "table: t\ndefault_order_by: season\nfields:\n  season: {name: season, query: {type: Range, min: season_min, max: season_max}}\n  tag: {name: tag, query: {type: StringTag}}\n  team: {name: team, query: {type: StringTag}}\n  text: {name: text, query: {type: Fulltext, lang: english}}\n",
+        )
+        .unwrap()
+    }
+
+    fn facet(
+        query: &str,
+        facet: &str,
+        exclude_own_filter: bool,
+    ) -> Result<PreparedQuery, CompassError> {
+        facet_query(
+            &schema(),
+            &SearchParams::parse(query).unwrap(),
+            facet,
+            &FacetOptions {
+                limit: 5,
+                exclude_own_filter,
+            },
+        )
+    }
+
+    fn params(prepared: &PreparedQuery) -> Vec<String> {
+        prepared.params.iter().map(|p| format!("{:?}", p)).collect()
+    }
+
+    #[test]
+    fn other_bindings_come_after_the_facets_own() {
+        let prepared = facet("tag=a&text=cat", "tag", false).unwrap();
+        assert_eq!(
+            prepared.query,
+            format!(
+                "SELECT value, COUNT(DISTINCT doc_id) FROM t CROSS JOIN LATERAL jsonb_path_query(object, CAST($2::text AS JSONPATH)) AS value WHERE object @@ CAST($1 AS JSONPATH) AND {} GROUP BY value ORDER BY COUNT(DISTINCT doc_id) DESC, value LIMIT $3",
+                TEXT
+            )
+        );
+        assert_eq!(
+            params(&prepared),
+            vec![
+                r#""(($.\"tag\" == \"a\"))""#,
+                r#""$.\"tag\"[*]""#,
+                "5",
+                r#""cat""#
+            ]
+        );
+    }
+
+    #[test]
+    fn excluding_own_filter_drops_negations_too() {
+        let prepared = facet("tag!=b&tag=a&team=x&text=cat", "tag", true).unwrap();
+        assert!(prepared.query.contains(TEXT));
+        assert_eq!(params(&prepared)[0], r#""(($.\"team\" == \"x\"))""#);
+
+        let prepared = facet("tag!=b&team=x", "tag", false).unwrap();
+        assert_eq!(
+            params(&prepared)[0],
+            r#""(!(($.\"tag\" == \"b\")) && ($.\"team\" == \"x\"))""#
+        );
+    }
+
+    #[test]
+    fn only_tag_fields_can_be_faceted() {
+        assert!(matches!(
+            facet("", "season", false),
+            Err(CompassError::InvalidFacet(f)) if f == "season"
+        ));
+        assert!(matches!(
+            facet("", "text", false),
+            Err(CompassError::InvalidFacet(_))
+        ));
+        assert!(matches!(
+            facet("", "nope", false),
+            Err(CompassError::FieldNotFound)
+        ));
+    }
+}
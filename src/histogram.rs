@@ -0,0 +1,253 @@
+use super::*;
+
+use db::PreparedQuery;
+
+use postgres::types::ToSql;
+use postgres::types::Type as PostgresType;
+use postgres::{Client, Row, Statement};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalendarInterval {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl CalendarInterval {
+    fn unit(&self) -> &'static str {
+        match self {
+            CalendarInterval::Hour => "hour",
+            CalendarInterval::Day => "day",
+            CalendarInterval::Week => "week",
+            CalendarInterval::Month => "month",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistogramBuckets {
+    /// buckets of a fixed width, starting at multiples of it. works on range fields and timestamps (in their stored unit)
+    Width(f64),
+    /// buckets lined up with the calendar, in UTC. only works on timestamps
+    Calendar(CalendarInterval),
+}
+
+/// A bucket's `start` is a number for fixed-width buckets, and an RFC3339 string for calendar ones.
+#[derive(Serialize, Debug, Clone)]
+pub struct HistogramBucket {
+    pub start: Value,
+    pub count: i64,
+}
+
+pub(crate) fn histogram_query(
+    schema: &Schema,
+    search_params: &SearchParams,
+    field: &str,
+    buckets: HistogramBuckets,
+) -> Result<PreparedQuery, CompassError> {
+    let schema_field = schema
+        .fields
+        .get(field)
+        .filter(|f| schema.returnable(f))
+        .ok_or(CompassError::FieldNotFound)?;
+    let invalid = || CompassError::InvalidHistogram(field.to_owned());
+
+    let millis = match schema_field.converter {
+        Some(ConverterSchema {
+            to: ConvertTo::Timestamp,
+            ..
+        }) => Some(false),
+        Some(ConverterSchema {
+            to: ConvertTo::TimestampMillis,
+            ..
+        }) => Some(true),
+        _ => None,
+    };
+
+    let value = match (&schema_field.query, millis) {
+        (_, Some(_)) => SortType::BigInt.expr(&FieldPath::new(field)),
+        (FieldQuery::Range { .. }, None) => SortType::Numeric.expr(&FieldPath::new(field)),
+        _ => return Err(invalid()),
+    };
+
+    let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+
+    let (bucket, label) = match (buckets, millis) {
+        (HistogramBuckets::Width(width), _) => {
+            if !(width > 0.0 && width.is_finite()) {
+                return Err(invalid());
+            }
+
+            params.push(Box::new(width.to_string()));
+            (
+                format!(
+                    "floor({} / CAST($2::text AS NUMERIC)) * CAST($2::text AS NUMERIC)",
+                    value
+                ),
+                "to_jsonb(bucket)".to_owned(),
+            )
+        }
+        (HistogramBuckets::Calendar(interval), Some(millis)) => (
+            format!(
+                "date_trunc('{}', to_timestamp({}{}) AT TIME ZONE 'UTC')",
+                interval.unit(),
+                value,
+                if millis { " / 1000.0" } else { "" }
+            ),
+            // same format converted timestamps are returned in
+            "to_jsonb(to_char(bucket, 'YYYY-MM-DD\"T\"HH24:MI:SS.MS\"Z\"'))".to_owned(),
+        ),
+        (HistogramBuckets::Calendar(_), None) => return Err(invalid()),
+    };
+
+    let (query, _, json_query, other_bindings) =
+        generate_where_params(schema, search_params, 2 + params.len(), false)?;
+
+    let query = format!(
+        "SELECT {}, COUNT(*) FROM (SELECT {} AS bucket FROM {} {}) AS buckets WHERE bucket IS NOT NULL GROUP BY bucket ORDER BY bucket",
+        label,
+        bucket,
+        escape::sql_identifier(&schema.table)?,
+        query
+    );
+
+    params.insert(0, Box::new(json_query));
+    params.extend(
+        other_bindings
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn ToSql + Sync + Send>),
+    );
+
+    Ok(PreparedQuery {
+        query,
+        types: vec![PostgresType::TEXT],
+        params,
+    })
+}
+
+pub(crate) fn histogram_buckets(rows: Vec<Row>) -> Vec<HistogramBucket> {
+    rows.into_iter()
+        .map(|x| HistogramBucket {
+            start: x.get::<usize, Value>(0),
+            count: x.get::<usize, i64>(1),
+        })
+        .collect()
+}
+
+/// Counts matching documents per bucket of `field`'s value, filtered the same way `json_search` would be. Empty buckets are left out.
+pub fn json_histogram(
+    client: &mut Client,
+    schema: &Schema,
+    fields: &HashMap<String, String>,
+    field: &str,
+    buckets: HistogramBuckets,
+) -> Result<Vec<HistogramBucket>, CompassError> {
+    let prepared = histogram_query(schema, &SearchParams::from_fields(fields)?, field, buckets)?;
+
+    let statement: Statement = client
+        .prepare_typed(prepared.query.as_str(), &prepared.types)
+        .map_err(CompassError::from)?;
+
+    let rows: Vec<Row> = client
+        .query(&statement, &prepared.params())
+        .map_err(CompassError::from)?;
+
+    Ok(histogram_buckets(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::from_yaml_str(
+            "table: t\ndefault_order_by: season\nfields:\n  season: {name: season, query: {type: Range, min: season_min, max: season_max}}\n  date: {name: date, converter: {from: DateTimeString, to: TimestampMillis}, query: {type: Range, min: after, max: before}}\n  tag: {name: tag, query: {type: StringTag}}\n  text: {name: text, query: {type: Fulltext, lang: english}}\n",
+        )
+        .unwrap()
+    }
+
+    fn histogram(field: &str, buckets: HistogramBuckets) -> Result<PreparedQuery, CompassError> {
+        histogram_query(
+            &schema(),
+            &SearchParams::parse("tag=a&text=cat").unwrap(),
+            field,
+            buckets,
+        )
+    }
+
+    fn params(prepared: &PreparedQuery) -> Vec<String> {
+        prepared.params.iter().map(|p| format!("{:?}", p)).collect()
+    }
+
+    #[test]
+    fn widths_are_bound_before_other_bindings() {
+        let prepared = histogram("date", HistogramBuckets::Width(2.5)).unwrap();
+        assert!(prepared.query.contains(
+            "floor((CASE WHEN (object #>> '{\"date\"}') ~ '^-?[0-9]+$' THEN (object #>> '{\"date\"}')::bigint END) / CAST($2::text AS NUMERIC)) * CAST($2::text AS NUMERIC) AS bucket FROM t WHERE"
+        ));
+        assert!(prepared
+            .query
+            .contains("websearch_to_tsquery('english',$3)) AS buckets"));
+        assert_eq!(
+            params(&prepared),
+            vec![r#""(($.\"tag\" == \"a\"))""#, r#""2.5""#, r#""cat""#]
+        );
+    }
+
+    #[test]
+    fn calendar_buckets_bind_nothing_of_their_own() {
+        let prepared =
+            histogram("date", HistogramBuckets::Calendar(CalendarInterval::Month)).unwrap();
+        assert!(prepared.query.starts_with(
+            "SELECT to_jsonb(to_char(bucket, 'YYYY-MM-DD\"T\"HH24:MI:SS.MS\"Z\"')), COUNT(*) FROM (SELECT date_trunc('month', to_timestamp("
+        ));
+        assert!(prepared
+            .query
+            .contains(" / 1000.0) AT TIME ZONE 'UTC') AS bucket"));
+        assert!(prepared
+            .query
+            .contains("websearch_to_tsquery('english',$2)) AS buckets"));
+        assert_eq!(
+            params(&prepared),
+            vec![r#""(($.\"tag\" == \"a\"))""#, r#""cat""#]
+        );
+    }
+
+    #[test]
+    fn widths_must_be_positive_and_finite() {
+        for width in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(
+                matches!(
+                    histogram("season", HistogramBuckets::Width(width)),
+                    Err(CompassError::InvalidHistogram(_))
+                ),
+                "{} was accepted",
+                width
+            );
+        }
+        assert!(histogram("season", HistogramBuckets::Width(0.5)).is_ok());
+    }
+
+    #[test]
+    fn calendar_buckets_need_timestamps() {
+        let month = HistogramBuckets::Calendar(CalendarInterval::Month);
+        assert!(matches!(
+            histogram("season", month),
+            Err(CompassError::InvalidHistogram(f)) if f == "season"
+        ));
+        assert!(matches!(
+            histogram("tag", HistogramBuckets::Width(1.0)),
+            Err(CompassError::InvalidHistogram(_))
+        ));
+        assert!(matches!(
+            histogram("nope", month),
+            Err(CompassError::FieldNotFound)
+        ));
+    }
+}
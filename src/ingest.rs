@@ -0,0 +1,136 @@
+use super::*;
+
+use convert::{apply_converters, converters};
+
+use postgres::binary_copy::BinaryCopyInWriter;
+use postgres::types::Type as PostgresType;
+use postgres::Client;
+
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+/// Applies the schema's converters to a raw document, producing exactly what gets stored.
+pub fn prepare_document(schema: &Schema, mut doc: Value) -> Result<Value, CompassError> {
+    apply_converters(&converters(schema), &mut doc)?;
+    Ok(doc)
+}
+
+/// Converts and inserts a single document. Fails if `doc_id` already exists.
+pub fn insert_document(
+    client: &mut Client,
+    schema: &Schema,
+    doc_id: Uuid,
+    doc: Value,
+) -> Result<(), CompassError> {
+    let doc = prepare_document(schema, doc)?;
+
+    client.execute(
+        format!(
+            "INSERT INTO {} (doc_id, object) VALUES ($1, $2)",
+            escape::sql_identifier(&schema.table)?
+        )
+        .as_str(),
+        &[&doc_id, &doc],
+    )?;
+
+    Ok(())
+}
+
+/// Converts and inserts documents, replacing any that already exist, in a single transaction.
+/// If any document fails to convert, nothing is written. Returns the number of rows written.
+pub fn upsert_documents<I>(
+    client: &mut Client,
+    schema: &Schema,
+    docs: I,
+) -> Result<u64, CompassError>
+where
+    I: IntoIterator<Item = (Uuid, Value)>,
+{
+    let converters = converters(schema);
+    let query = format!(
+        "INSERT INTO {} (doc_id, object) VALUES ($1, $2) ON CONFLICT (doc_id) DO UPDATE SET object = EXCLUDED.object",
+        escape::sql_identifier(&schema.table)?
+    );
+
+    let mut transaction = client.transaction()?;
+    let statement = transaction.prepare(query.as_str())?;
+
+    let mut written = 0;
+    for (doc_id, mut doc) in docs {
+        apply_converters(&converters, &mut doc)?;
+        written += transaction.execute(&statement, &[&doc_id, &doc])?;
+    }
+
+    transaction.commit()?;
+    Ok(written)
+}
+
+/// How many documents `bulk_load` sends per COPY, unless told otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+/// What happened during a `bulk_load`: how many rows made it in, which documents were rejected by their converters, and the error that stopped the load, if one did.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub written: u64,
+    pub rejected: Vec<(Uuid, CompassError)>,
+    /// the database error that failed a batch. everything counted in `written` is still committed, nothing after it is
+    pub failed_batch: Option<CompassError>,
+}
+
+/// Streams documents into the schema's table with `COPY ... FROM STDIN BINARY`, `batch_size` at a time.
+/// Documents that fail to convert are skipped and reported instead of aborting the load.
+/// Each batch is its own COPY, so if the database rejects one (say, on a duplicate `doc_id`), the load stops there, the batches before it stay written, and the error ends up in the report's `failed_batch`.
+pub fn bulk_load<I>(
+    client: &mut Client,
+    schema: &Schema,
+    docs: I,
+    batch_size: usize,
+) -> Result<LoadReport, CompassError>
+where
+    I: IntoIterator<Item = (Uuid, Value)>,
+{
+    let converters = converters(schema);
+    let query = format!(
+        "COPY {} (doc_id, object) FROM STDIN BINARY",
+        escape::sql_identifier(&schema.table)?
+    );
+
+    let mut report = LoadReport::default();
+    let mut docs = docs.into_iter().peekable();
+
+    while docs.peek().is_some() {
+        let batch = docs.by_ref().take(batch_size.max(1));
+        match copy_batch(client, &query, &converters, batch, &mut report.rejected) {
+            Ok(written) => report.written += written,
+            Err(e) => {
+                report.failed_batch = Some(e);
+                break;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn copy_batch(
+    client: &mut Client,
+    query: &str,
+    converters: &HashMap<String, ConverterSchema>,
+    batch: impl Iterator<Item = (Uuid, Value)>,
+    rejected: &mut Vec<(Uuid, CompassError)>,
+) -> Result<u64, CompassError> {
+    let writer = client.copy_in(query)?;
+    let mut writer = BinaryCopyInWriter::new(writer, &[PostgresType::UUID, PostgresType::JSONB]);
+
+    for (doc_id, mut doc) in batch {
+        match apply_converters(converters, &mut doc) {
+            Ok(()) => writer.write(&[&doc_id, &doc])?,
+            Err(e) => rejected.push((doc_id, e)),
+        }
+    }
+
+    Ok(writer.finish()?)
+}
@@ -1,6 +1,34 @@
+mod convert;
 mod db;
+#[cfg(feature = "async_support")]
+mod db_async;
+mod ddl;
 pub mod err;
+mod escape;
+mod expr;
+mod facets;
+mod histogram;
+mod ingest;
+mod params;
+mod parse;
+mod projection;
+pub mod query;
+mod relevance;
 pub mod schema;
+mod sort;
+mod validate;
+mod visibility;
 pub use db::*;
+#[cfg(feature = "async_support")]
+pub use db_async::*;
+pub use ddl::*;
 pub use err::*;
+pub use facets::*;
+pub use histogram::*;
+pub use ingest::*;
+pub use params::*;
+pub use parse::*;
+pub use query::*;
 pub use schema::*;
+pub use sort::*;
+pub use validate::*;
@@ -0,0 +1,40 @@
+use compass::{generate_ddl, CompassError, Schema};
+
+use std::env;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: compass <command> <schema.yaml>");
+    eprintln!();
+    eprintln!("commands:");
+    eprintln!("    ddl         print CREATE TABLE and CREATE INDEX statements for the schema");
+    eprintln!("    validate    check the schema for problems");
+    process::exit(2);
+}
+
+fn fail(err: CompassError) -> ! {
+    match err {
+        CompassError::InvalidSchema(problems) => {
+            for problem in problems {
+                eprintln!("{}", problem);
+            }
+        }
+        err => eprintln!("{}", err),
+    }
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, path) = match args.as_slice() {
+        [command, path] if command == "ddl" || command == "validate" => (command.as_str(), path),
+        _ => usage(),
+    };
+
+    let schema = Schema::from_path(path).unwrap_or_else(|e| fail(e));
+
+    match command {
+        "ddl" => println!("{}", generate_ddl(&schema).unwrap_or_else(|e| fail(e))),
+        _ => println!("{} is valid", path),
+    }
+}
@@ -0,0 +1,201 @@
+use super::*;
+
+use std::collections::HashMap;
+
+/// How a filter key that's given more than once combines, i.e. whether `?tag=a&tag=b` means both tags or either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatedKeys {
+    #[default]
+    And,
+    Or,
+}
+
+/// The parameters of a search, with the ones compass handles itself split out from the filters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchParams {
+    /// every parameter that isn't reserved, in order. keys can repeat
+    pub filters: Vec<(String, String)>,
+    pub sortby: Option<String>,
+    pub sortorder: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// the rest of [`RESERVED_PARAMETERS`], like `cursor` and `highlight`
+    pub options: HashMap<String, String>,
+    pub repeated: RepeatedKeys,
+}
+
+impl SearchParams {
+    /// Parses a raw query string, like `tag=a&tag=b&sortby=season`, with or without the leading `?`. Keys and values are percent-decoded, and `+` is a space.
+    /// If a reserved parameter is given more than once, the last one wins.
+    pub fn parse(query: &str) -> Result<SearchParams, CompassError> {
+        let mut params = SearchParams::default();
+
+        for pair in query
+            .strip_prefix('?')
+            .unwrap_or(query)
+            .split('&')
+            .filter(|p| !p.is_empty())
+        {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            params.insert(&percent_decode(k), &percent_decode(v))?;
+        }
+
+        Ok(params)
+    }
+
+    /// Builds parameters out of a map, like the one `json_search` takes. Filters are sorted by key, so queries built from the same map always come out the same.
+    pub fn from_fields(fields: &HashMap<String, String>) -> Result<SearchParams, CompassError> {
+        let mut keys: Vec<&String> = fields.keys().collect();
+        keys.sort();
+
+        let mut params = SearchParams::default();
+        for k in keys {
+            params.insert(k, &fields[k])?;
+        }
+
+        Ok(params)
+    }
+
+    fn insert(&mut self, key: &str, value: &str) -> Result<(), CompassError> {
+        match key {
+            "sortby" => self.sortby = Some(value.to_owned()),
+            "sortorder" => self.sortorder = Some(value.to_owned()),
+            "limit" => {
+                self.limit = Some(
+                    value
+                        .parse::<i64>()
+                        .map_err(CompassError::InvalidNumberError)?,
+                )
+            }
+            "offset" => {
+                self.offset = Some(
+                    value
+                        .parse::<i64>()
+                        .map_err(CompassError::InvalidNumberError)?,
+                )
+            }
+            k if RESERVED_PARAMETERS.contains(&k) => {
+                self.options.insert(key.to_owned(), value.to_owned());
+            }
+            _ => self.filters.push((key.to_owned(), value.to_owned())),
+        }
+
+        Ok(())
+    }
+
+    /// Filter keys, each once, in the order they first show up.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = Vec::new();
+        for (k, _) in &self.filters {
+            if !keys.contains(&k.as_str()) {
+                keys.push(k);
+            }
+        }
+        keys
+    }
+
+    /// Every value given for the filter `key`, in order.
+    pub fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.filters
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// bad escapes are kept as they are rather than failing the whole search, the same way browsers treat them
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                out.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("a+b"), "a b");
+        assert_eq!(percent_decode("a%2Bb"), "a+b");
+        assert_eq!(percent_decode("%c3%A9"), "é");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("a%2"), "a%2");
+        assert_eq!(percent_decode("%zz%2g"), "%zz%2g");
+        assert_eq!(percent_decode("%%41"), "%A");
+        assert_eq!(percent_decode("%ff"), "\u{fffd}");
+    }
+
+    #[test]
+    fn parsing_splits_out_reserved_parameters() {
+        let params =
+            SearchParams::parse("?tag=a&sortby=season&tag=b&limit=5&limit=10&highlight=true&x")
+                .unwrap();
+
+        assert_eq!(
+            params.filters,
+            vec![
+                ("tag".to_owned(), "a".to_owned()),
+                ("tag".to_owned(), "b".to_owned()),
+                ("x".to_owned(), "".to_owned()),
+            ]
+        );
+        assert_eq!(params.sortby.as_deref(), Some("season"));
+        assert_eq!(params.limit, Some(10));
+        assert_eq!(params.offset, None);
+        assert_eq!(params.options["highlight"], "true");
+        assert_eq!(params.keys(), vec!["tag", "x"]);
+        assert_eq!(params.values("tag").collect::<Vec<&str>>(), vec!["a", "b"]);
+
+        assert!(matches!(
+            SearchParams::parse("limit=ten"),
+            Err(CompassError::InvalidNumberError(_))
+        ));
+        assert_eq!(SearchParams::parse("").unwrap(), SearchParams::default());
+    }
+
+    #[test]
+    fn repeated_keys_combine_as_configured() {
+        let schema = Schema::from_yaml_str(
+            "table: t\ndefault_order_by: tag\nfields:\n  tag: {name: tag, query: {type: StringTag}}\n  n: {name: n, query: {type: NumericTag}}\n",
+        )
+        .unwrap();
+        let mut params = SearchParams::parse("tag=a&n=1&tag=b").unwrap();
+
+        assert_eq!(
+            Query::from_params(&schema, &params)
+                .unwrap()
+                .to_jsonpath()
+                .unwrap(),
+            r#"((($."tag" == "a") && ($."tag" == "b")) && (($."n" == 1) || ($."n" == "1")))"#
+        );
+
+        params.repeated = RepeatedKeys::Or;
+        assert_eq!(
+            Query::from_params(&schema, &params)
+                .unwrap()
+                .to_jsonpath()
+                .unwrap(),
+            r#"((($."tag" == "a") || ($."tag" == "b")) && (($."n" == 1) || ($."n" == "1")))"#
+        );
+    }
+}
@@ -0,0 +1,845 @@
+use super::*;
+
+use expr::MAX_NESTING;
+use visibility::targets_filterable;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+enum ListToken {
+    Item(String),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+// a backslash right before a separator (or, with parentheses on, a parenthesis) makes it part of the value instead. any other backslash is left alone, since regex values need theirs
+fn tokenize_list(q: &str, lists: &ValueLists) -> Result<Vec<ListToken>, CompassError> {
+    // validation catches these, but a schema doesn't have to have been validated. an empty separator would match forever
+    if lists.and.is_empty() || lists.or.is_empty() {
+        return Err(CompassError::InvalidSchema(vec![SchemaProblem {
+            field: None,
+            kind: SchemaProblemKind::InvalidSeparators,
+        }]));
+    }
+
+    let mut escapable = vec![lists.and.as_str(), lists.or.as_str()];
+    if lists.parentheses {
+        escapable.extend(&["(", ")"]);
+    }
+
+    let mut tokens = Vec::new();
+    let mut item = String::new();
+    let mut rest = q;
+
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('\\') {
+            if let Some(literal) = escapable.iter().find(|s| r.starts_with(**s)) {
+                item.push_str(literal);
+                rest = &r[literal.len()..];
+                continue;
+            }
+        }
+
+        let separator = if rest.starts_with(&lists.and) {
+            Some((ListToken::And, lists.and.len()))
+        } else if rest.starts_with(&lists.or) {
+            Some((ListToken::Or, lists.or.len()))
+        } else if lists.parentheses && rest.starts_with('(') {
+            Some((ListToken::Open, 1))
+        } else if lists.parentheses && rest.starts_with(')') {
+            Some((ListToken::Close, 1))
+        } else {
+            None
+        };
+
+        match separator {
+            Some((token, len)) => {
+                if !item.is_empty() {
+                    tokens.push(ListToken::Item(std::mem::take(&mut item)));
+                }
+                tokens.push(token);
+                rest = &rest[len..];
+            }
+            None => {
+                let c = rest.chars().next().unwrap();
+                item.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    if !item.is_empty() {
+        tokens.push(ListToken::Item(item));
+    }
+
+    Ok(tokens)
+}
+
+// and binds tighter than or, so a_and_b_or_c is (a && b) || c
+struct ListParser<'a> {
+    tokens: Vec<ListToken>,
+    pos: usize,
+    depth: usize,
+    filter_gen: &'a dyn Fn(&str) -> Result<Query, CompassError>,
+}
+
+impl<'a> ListParser<'a> {
+    fn peek(&self) -> Option<&ListToken> {
+        self.tokens.get(self.pos)
+    }
+
+    // a separator at the very end is ignored, like in a_or_
+    fn ends_after_separator(&self) -> bool {
+        self.pos + 1 >= self.tokens.len()
+    }
+
+    fn or(&mut self) -> Result<Query, CompassError> {
+        let mut alternatives = vec![self.and()?];
+        while self.peek() == Some(&ListToken::Or) && !self.ends_after_separator() {
+            self.pos += 1;
+            alternatives.push(self.and()?);
+        }
+        if self.peek() == Some(&ListToken::Or) {
+            self.pos += 1;
+        }
+        Ok(collapse(alternatives, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query, CompassError> {
+        let mut conjunction = vec![self.item()?];
+        while self.peek() == Some(&ListToken::And) && !self.ends_after_separator() {
+            self.pos += 1;
+            conjunction.push(self.item()?);
+        }
+        if self.peek() == Some(&ListToken::And) {
+            self.pos += 1;
+        }
+        Ok(collapse(conjunction, Query::And))
+    }
+
+    fn item(&mut self) -> Result<Query, CompassError> {
+        match self.peek().cloned() {
+            Some(ListToken::Item(x)) => {
+                self.pos += 1;
+                (self.filter_gen)(&x)
+            }
+            Some(ListToken::Open) => {
+                if self.depth >= MAX_NESTING {
+                    return Err(CompassError::InvalidExpression(format!(
+                        "value list nested more than {} levels deep",
+                        MAX_NESTING
+                    )));
+                }
+
+                self.pos += 1;
+                self.depth += 1;
+                let q = self.or()?;
+                self.depth -= 1;
+                if self.peek() != Some(&ListToken::Close) {
+                    return Err(CompassError::InvalidExpression(
+                        "missing ')' in value list".to_owned(),
+                    ));
+                }
+                self.pos += 1;
+                Ok(q)
+            }
+            // nothing between two separators
+            _ => (self.filter_gen)(""),
+        }
+    }
+}
+
+// values are run through the field's converter (if it has one) before they're handed to filter_gen, so they can be written the way results show them
+fn parse_query_list<F>(
+    q: &str,
+    lists: &ValueLists,
+    converter: Option<&ConverterSchema>,
+    filter_gen: F,
+) -> Result<Query, CompassError>
+where
+    F: Fn(&str) -> Result<Query, CompassError>,
+{
+    let filter_gen = |x: &str| filter_gen(&convert(x, converter)?);
+
+    let mut parser = ListParser {
+        tokens: tokenize_list(q, lists)?,
+        pos: 0,
+        depth: 0,
+        filter_gen: &filter_gen,
+    };
+
+    let query = parser.or()?;
+    if parser.peek().is_some() {
+        return Err(CompassError::InvalidExpression(format!(
+            "unbalanced parentheses in '{}'",
+            q
+        )));
+    }
+
+    Ok(query)
+}
+
+fn convert(x: &str, converter: Option<&ConverterSchema>) -> Result<String, CompassError> {
+    match converter {
+        Some(conv) => conv.query_value(x),
+        None => Ok(x.to_owned()),
+    }
+}
+
+pub(crate) fn collapse<F>(mut qs: Vec<Query>, combine: F) -> Query
+where
+    F: Fn(Vec<Query>) -> Query,
+{
+    if qs.len() == 1 {
+        qs.remove(0)
+    } else {
+        combine(qs)
+    }
+}
+
+// if it looks like an int, make it an int! because we can't specificy all the metadata fields in the schema. yeah i don't like this either
+fn ambiguous_value(path: &FieldPath, x: &str) -> Query {
+    let mut filter: Vec<Query> = Vec::new();
+
+    if let Ok(n) = x.parse::<i64>() {
+        filter.push(Query::eq(path, Literal::Int(n)));
+    } else if let Ok(n) = x.parse::<Decimal>() {
+        filter.push(Query::eq(path, Literal::Decimal(n)));
+    } else if let Ok(n) = x.parse::<bool>() {
+        filter.push(Query::eq(path, Literal::Bool(n)));
+    } else if x == "exists" {
+        filter.push(Query::Exists(path.clone()));
+    } else if x == "notexists" {
+        filter.push(Query::Not(Box::new(Query::Exists(path.clone()))));
+    }
+
+    filter.push(Query::eq(path, Literal::String(x.to_owned())));
+
+    Query::Or(filter)
+}
+
+fn existence(path: &FieldPath, x: &str) -> Option<Query> {
+    if x == "exists" {
+        Some(Query::Exists(path.clone()))
+    } else if x == "notexists" {
+        Some(Query::Not(Box::new(Query::Exists(path.clone()))))
+    } else {
+        None
+    }
+}
+
+fn number(x: &str, number: NumberType) -> Result<Literal, CompassError> {
+    match number {
+        NumberType::Integer => Ok(Literal::Int(
+            x.parse::<i64>().map_err(CompassError::InvalidNumberError)?,
+        )),
+        NumberType::Decimal => Ok(Literal::Decimal(x.parse::<Decimal>()?)),
+    }
+}
+
+fn alias(n: i64, number: NumberType) -> Literal {
+    match number {
+        NumberType::Integer => Literal::Int(n),
+        NumberType::Decimal => Literal::Decimal(Decimal::from(n)),
+    }
+}
+
+fn lower_bound(path: &FieldPath, value: Literal, inclusive: bool) -> Query {
+    Query::Compare {
+        path: path.clone(),
+        op: if inclusive {
+            Operator::Ge
+        } else {
+            Operator::Gt
+        },
+        value,
+    }
+}
+
+fn upper_bound(path: &FieldPath, value: Literal, inclusive: bool) -> Query {
+    Query::Compare {
+        path: path.clone(),
+        op: if inclusive {
+            Operator::Le
+        } else {
+            Operator::Lt
+        },
+        value,
+    }
+}
+
+fn array_items(x: &str, items: ArrayItems) -> Result<Vec<Literal>, CompassError> {
+    match items {
+        ArrayItems::String => Ok(vec![Literal::String(x.to_owned())]),
+        ArrayItems::Number => Ok(vec![Literal::Decimal(x.parse::<Decimal>()?)]),
+        ArrayItems::Ambiguous => {
+            let mut values = Vec::new();
+            if let Ok(n) = x.parse::<Decimal>() {
+                values.push(Literal::Decimal(n));
+            }
+            values.push(Literal::String(x.to_owned()));
+            Ok(values)
+        }
+    }
+}
+
+// split on commas, except `\,`, which is a comma inside an element
+fn array_elements(list: &str) -> Vec<String> {
+    let mut elements = vec![String::new()];
+    let mut chars = list.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                elements.last_mut().unwrap().push(',');
+            }
+            ',' => elements.push(String::new()),
+            c => elements.last_mut().unwrap().push(c),
+        }
+    }
+
+    elements
+}
+
+// a comma-separated list of elements, optionally starting with `any:`, `all:` or `none:`
+fn array_filter(
+    path: &FieldPath,
+    x: &str,
+    items: ArrayItems,
+    quantifier: Quantifier,
+) -> Result<Query, CompassError> {
+    let (quantifier, list) = if let Some(l) = x.strip_prefix("any:") {
+        (Quantifier::Any, l)
+    } else if let Some(l) = x.strip_prefix("all:") {
+        (Quantifier::All, l)
+    } else if let Some(l) = x.strip_prefix("none:") {
+        (Quantifier::None, l)
+    } else {
+        (quantifier, x)
+    };
+
+    let elements = array_elements(list)
+        .iter()
+        .map(|e| array_items(e.trim(), items))
+        .collect::<Result<Vec<Vec<Literal>>, CompassError>>()?;
+
+    let any_of = |values: Vec<Literal>| Query::AnyOf {
+        path: path.clone(),
+        values,
+    };
+
+    Ok(match quantifier {
+        Quantifier::Any => any_of(elements.into_iter().flatten().collect()),
+        Quantifier::All => collapse(elements.into_iter().map(any_of).collect(), Query::And),
+        Quantifier::None => Query::Not(Box::new(any_of(elements.into_iter().flatten().collect()))),
+    })
+}
+
+// `3`, or a range like `2..5`, `2..` or `..5`, with the ends included as `bounds` says
+fn length_filter(path: &FieldPath, x: &str, bounds: RangeBounds) -> Result<Query, CompassError> {
+    let length = |op, n: &str| -> Result<Query, CompassError> {
+        Ok(Query::Length {
+            path: path.clone(),
+            op,
+            value: n.parse::<i64>().map_err(CompassError::InvalidNumberError)?,
+        })
+    };
+
+    let min_op = if bounds.min_inclusive() {
+        Operator::Ge
+    } else {
+        Operator::Gt
+    };
+    let max_op = if bounds.max_inclusive() {
+        Operator::Le
+    } else {
+        Operator::Lt
+    };
+
+    match x.split_once("..") {
+        Some((min, "")) => length(min_op, min),
+        Some(("", max)) => length(max_op, max),
+        Some((min, max)) => Ok(Query::And(vec![length(min_op, min)?, length(max_op, max)?])),
+        None => length(Operator::Eq, x),
+    }
+}
+
+// with a precision, a decimal matches anything that rounds to it, rather than only exactly itself
+fn number_eq(
+    path: &FieldPath,
+    value: Literal,
+    precision: Option<u32>,
+) -> Result<Query, CompassError> {
+    match (&value, precision) {
+        (Literal::Decimal(d), Some(p)) => {
+            let range = d
+                .rounding_range(p)
+                .ok_or_else(|| CompassError::InvalidDecimalError(d.to_string()))?;
+            Ok(Query::And(vec![
+                lower_bound(path, Literal::Decimal(range.low), range.low_inclusive),
+                upper_bound(path, Literal::Decimal(range.high), range.high_inclusive),
+            ]))
+        }
+        _ => Ok(Query::eq(path, value)),
+    }
+}
+
+pub fn generate_one_field(
+    schema: &Schema,
+    v: &str,
+    field: (&String, FieldQuery),
+) -> Result<Query, CompassError> {
+    let path = FieldPath::new(field.0);
+    let lists = &schema.value_lists;
+    let converter = schema
+        .fields
+        .get(field.0)
+        .and_then(|f| f.converter.as_ref());
+
+    match field.1 {
+        FieldQuery::Range {
+            ref aliases,
+            number: kind,
+            precision,
+            bounds,
+            ..
+        } => {
+            let value = |x: &str| {
+                let x = convert(x, converter)?;
+                match aliases.get(&x.to_uppercase()) {
+                    Some(n) => Ok(alias(*n, kind)),
+                    None => number(&x, kind),
+                }
+            };
+
+            // if something gets directly found as a 'Range' query, it means someone used season=18 instead of like, season_min=16. so it actually, counter-intuitively, is like a numeric tag!
+            // unless it's season=16..18, which is the same as season_min=16&season_max=18. either end can be left off
+            // converted per bound, since the converter wouldn't know what to do with both at once
+            parse_query_list(v, lists, None, |x| {
+                if let Some(q) = existence(&path, x) {
+                    Ok(q)
+                } else if let Some((min, max)) = x.split_once("..").filter(|b| b != &("", "")) {
+                    let mut filters = Vec::new();
+                    if !min.is_empty() {
+                        filters.push(lower_bound(&path, value(min)?, bounds.min_inclusive()));
+                    }
+                    if !max.is_empty() {
+                        filters.push(upper_bound(&path, value(max)?, bounds.max_inclusive()));
+                    }
+                    Ok(Query::And(filters))
+                } else {
+                    number_eq(&path, value(x)?, precision)
+                }
+            })
+        }
+        FieldQuery::Min {
+            number: kind,
+            inclusive,
+        } => parse_query_list(v, lists, converter, |x| {
+            Ok(lower_bound(&path, number(x, kind)?, inclusive))
+        }),
+        FieldQuery::Max {
+            number: kind,
+            inclusive,
+        } => parse_query_list(v, lists, converter, |x| {
+            Ok(upper_bound(&path, number(x, kind)?, inclusive))
+        }),
+        FieldQuery::Bool => parse_query_list(v, lists, converter, |x| {
+            if let Some(q) = existence(&path, x) {
+                Ok(q)
+            } else {
+                Ok(Query::eq(
+                    &path,
+                    Literal::Bool(x.parse::<bool>().map_err(CompassError::InvalidBoolError)?),
+                ))
+            }
+        }),
+        FieldQuery::AmbiguousTag | FieldQuery::Nested => {
+            parse_query_list(v, lists, converter, |x| Ok(ambiguous_value(&path, x)))
+        }
+        FieldQuery::NumericTag {
+            ref aliases,
+            number: kind,
+            precision,
+        } => parse_query_list(v, lists, converter, |x| {
+            if let Some(q) = existence(&path, x) {
+                return Ok(q);
+            }
+
+            let n = match aliases.get(&x.to_uppercase()) {
+                Some(n) => alias(*n, kind),
+                None => number(x, kind)?,
+            };
+
+            // numbers sometimes end up stored as strings
+            let text = n.to_jsonpath();
+            Ok(Query::Or(vec![
+                number_eq(&path, n, precision)?,
+                Query::eq(&path, Literal::String(text)),
+            ]))
+        }),
+        FieldQuery::StringTag {
+            ref aliases,
+            case_insensitive,
+            accent_insensitive,
+        } => parse_query_list(v, lists, converter, |x| {
+            let x = aliases
+                .get(&x.to_uppercase())
+                .map(String::as_str)
+                .unwrap_or(x);
+
+            if !case_insensitive && !accent_insensitive {
+                return Ok(Query::eq(&path, Literal::String(x.to_owned())));
+            }
+
+            // JSONPath can't compare strings loosely, but a regex anchored at both ends can
+            let pattern = if accent_insensitive {
+                escape::regex_literal_ignoring_accents(x)
+            } else {
+                escape::regex_literal(x)
+            };
+
+            Ok(Query::Regex {
+                path: path.clone(),
+                pattern: format!("^{}$", pattern),
+                flags: if case_insensitive { "i" } else { "" }.to_owned(),
+            })
+        }),
+        FieldQuery::Pattern {
+            mode,
+            case_insensitive,
+        } => parse_query_list(v, lists, converter, |x| {
+            if let Some(q) = existence(&path, x) {
+                return Ok(q);
+            }
+
+            let flags = if case_insensitive { "i" } else { "" }.to_owned();
+            Ok(match mode {
+                // starts with can't ignore case, so that takes a regex
+                PatternMode::Prefix if !case_insensitive => Query::StartsWith {
+                    path: path.clone(),
+                    prefix: x.to_owned(),
+                },
+                PatternMode::Prefix => Query::Regex {
+                    path: path.clone(),
+                    pattern: format!("^{}", escape::regex_literal(x)),
+                    flags,
+                },
+                PatternMode::Substring => Query::Regex {
+                    path: path.clone(),
+                    pattern: escape::regex_literal(x),
+                    flags,
+                },
+                PatternMode::Regex => Query::Regex {
+                    path: path.clone(),
+                    pattern: x.to_owned(),
+                    flags,
+                },
+            })
+        }),
+        FieldQuery::Array {
+            items, quantifier, ..
+        } => parse_query_list(v, lists, converter, |x| {
+            if let Some(q) = existence(&path, x) {
+                Ok(q)
+            } else {
+                array_filter(&path, x, items, quantifier)
+            }
+        }),
+        FieldQuery::ArrayLength { bounds } => {
+            parse_query_list(v, lists, None, |x| length_filter(&path, x, bounds))
+        }
+        FieldQuery::Fulltext {
+            ref lang,
+            ref syntax,
+            ref target,
+            ref targets,
+            ..
+        } => Ok(Query::Fulltext {
+            targets: fulltext_targets(field.0, target.as_deref(), targets),
+            lang: lang.to_owned(),
+            syntax: *syntax,
+            query: v.to_owned(),
+        }),
+        FieldQuery::Not(inner) => Ok(Query::Not(Box::new(generate_one_field(
+            schema,
+            v,
+            (field.0, *inner),
+        )?))),
+    }
+}
+
+/// Finds which schema field a URL parameter refers to, and how it should be queried. Fields that can't be filtered on aren't found, and neither are full-text fields searching a target that can't be.
+pub fn resolve_field(schema: &Schema, k: &str) -> Option<(String, FieldQuery)> {
+    resolve_any_field(schema, k).filter(|(name, query)| {
+        matches!(field_for_path(schema, &FieldPath::new(name)), Some(f) if schema.filterable(f))
+            && targets_filterable(schema, name, query)
+    })
+}
+
+fn resolve_any_field(schema: &Schema, k: &str) -> Option<(String, FieldQuery)> {
+    match schema.fields.get(k) {
+        // find field from URL query in schema
+        Some(field) => {
+            Some((k.to_owned(), field.query.clone())) // oh, we found it by name. cool, return that
+        }
+        None => {
+            let find_nested = |k: &str| {
+                schema.fields.iter().find_map(|f| {
+                    match f.1.query {
+                        // oops we couldn't find it; let's see if it's a field that can have multiple names like range or metadata
+                        FieldQuery::Range {
+                            ref min,
+                            ref max,
+                            number,
+                            bounds,
+                            ..
+                        } => {
+                            if k == min {
+                                Some((
+                                    f.0.to_owned(),
+                                    FieldQuery::Min {
+                                        number,
+                                        inclusive: bounds.min_inclusive(),
+                                    },
+                                ))
+                            } else if k == max {
+                                Some((
+                                    f.0.to_owned(),
+                                    FieldQuery::Max {
+                                        number,
+                                        inclusive: bounds.max_inclusive(),
+                                    },
+                                ))
+                            } else {
+                                None
+                            }
+                        }
+                        FieldQuery::Array {
+                            length: Some(ref length),
+                            bounds,
+                            ..
+                        } if k == length => {
+                            Some((f.0.to_owned(), FieldQuery::ArrayLength { bounds }))
+                        }
+                        FieldQuery::Nested => {
+                            if k.split('.').next().unwrap() == f.0 {
+                                Some((k.to_owned(), FieldQuery::Nested))
+                            } else {
+                                None
+                            }
+                        }
+                        _ => None,
+                    }
+                })
+            };
+
+            if let Some(f) = k.strip_suffix('!') {
+                // THE GOOD CODE DETECTED (JK IT'S VERY BAD THIS IS THE WORST THING I'VE EVER WRITTEN AND I'M DYING INSIDE)
+                schema
+                    .fields
+                    .get(f)
+                    .map(|field| (f.to_owned(), FieldQuery::Not(Box::new(field.query.clone()))))
+                    .or_else(|| find_nested(f).map(|(a, b)| (a, FieldQuery::Not(Box::new(b)))))
+            } else {
+                find_nested(k)
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Builds a query out of URL parameters. Parameters that don't match any field in the schema are ignored.
+    pub fn from_fields(
+        schema: &Schema,
+        fields: &HashMap<String, String>,
+    ) -> Result<Query, CompassError> {
+        Query::from_params(schema, &SearchParams::from_fields(fields)?)
+    }
+
+    /// Like [`Query::from_fields`]. Keys given more than once are combined as `params.repeated` says.
+    pub fn from_params(schema: &Schema, params: &SearchParams) -> Result<Query, CompassError> {
+        let combine: fn(Vec<Query>) -> Query = match params.repeated {
+            RepeatedKeys::And => Query::And,
+            RepeatedKeys::Or => Query::Or,
+        };
+
+        let mut filters = Vec::new();
+
+        for k in params.keys() {
+            if let Some(field) = resolve_field(schema, k) {
+                let queries = params
+                    .values(k)
+                    .map(|v| generate_one_field(schema, v, (&field.0, field.1.clone())))
+                    .collect::<Result<Vec<Query>, CompassError>>()?;
+                filters.push(collapse(queries, combine));
+            }
+        }
+
+        if let Some(q) = params.options.get("q").filter(|q| !q.trim().is_empty()) {
+            filters.push(Query::parse_expression(schema, q)?);
+        }
+
+        Ok(Query::And(filters))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(yaml: &str) -> Schema {
+        Schema::from_yaml_str(yaml).unwrap()
+    }
+
+    fn filter(schema: &Schema, query: &str) -> String {
+        Query::from_params(schema, &SearchParams::parse(query).unwrap())
+            .unwrap()
+            .to_jsonpath()
+            .unwrap()
+    }
+
+    const ARRAYS: &str = "table: t\ndefault_order_by: n\nfields:\n  n: {name: n, query: {type: Range, min: n_min, max: n_max}}\n  tags: {name: tags, query: {type: Array, length: tag_count}}\n  kw: {name: kw, query: {type: Array, length: kw_count, bounds: Inclusive}}\n";
+
+    #[test]
+    fn array_elements_split_on_unescaped_commas() {
+        assert_eq!(array_elements("a,b"), vec!["a", "b"]);
+        assert_eq!(array_elements(r"a\,b,c"), vec!["a,b", "c"]);
+        assert_eq!(array_elements(r"a\b"), vec![r"a\b"]);
+        assert_eq!(array_elements(""), vec![""]);
+
+        assert_eq!(
+            filter(&schema(ARRAYS), r"tags=any:a\,b,c"),
+            r#"((exists($."tags"[*] ? (@ == "a,b" || @ == "c"))))"#
+        );
+    }
+
+    #[test]
+    fn length_ranges_follow_bounds_like_ranges_do() {
+        let s = schema(ARRAYS);
+        assert_eq!(
+            filter(&s, "n=16..18"),
+            r#"((($."n" > 16) && ($."n" < 18)))"#
+        );
+        assert_eq!(
+            filter(&s, "tag_count=16..18"),
+            r#"((($."tags".size() > 16) && ($."tags".size() < 18)))"#
+        );
+        assert_eq!(
+            filter(&s, "kw_count=16..18"),
+            r#"((($."kw".size() >= 16) && ($."kw".size() <= 18)))"#
+        );
+        assert_eq!(filter(&s, "kw_count=..3"), r#"(($."kw".size() <= 3))"#);
+        assert_eq!(filter(&s, "tag_count=3"), r#"(($."tags".size() == 3))"#);
+    }
+
+    fn list(q: &str, lists: &ValueLists) -> Result<String, CompassError> {
+        let path = FieldPath::new("x");
+        parse_query_list(q, lists, None, |x| {
+            Ok(Query::eq(&path, Literal::String(x.to_owned())))
+        })
+        .map(|q| q.to_jsonpath().unwrap())
+    }
+
+    fn with_parentheses() -> ValueLists {
+        ValueLists {
+            parentheses: true,
+            ..ValueLists::default()
+        }
+    }
+
+    #[test]
+    fn value_lists_and_binds_tighter_than_or() {
+        let lists = ValueLists::default();
+        assert_eq!(
+            list("a_and_b_or_c", &lists).unwrap(),
+            r#"((($."x" == "a") && ($."x" == "b")) || ($."x" == "c"))"#
+        );
+        assert_eq!(
+            list("a_or_b_and_c", &lists).unwrap(),
+            r#"(($."x" == "a") || (($."x" == "b") && ($."x" == "c")))"#
+        );
+        assert_eq!(
+            list("(a_or_b)_and_c", &with_parentheses()).unwrap(),
+            r#"((($."x" == "a") || ($."x" == "b")) && ($."x" == "c"))"#
+        );
+        // without parentheses on, they're just part of the value
+        assert_eq!(list("(a)", &lists).unwrap(), r#"($."x" == "(a)")"#);
+    }
+
+    #[test]
+    fn value_lists_escape_separators() {
+        let lists = ValueLists::default();
+        assert_eq!(
+            list(r"snake\_and_case", &lists).unwrap(),
+            r#"($."x" == "snake_and_case")"#
+        );
+        assert_eq!(
+            list(r"a\_or_b_or_c", &lists).unwrap(),
+            r#"(($."x" == "a_or_b") || ($."x" == "c"))"#
+        );
+        assert_eq!(list(r"a\b", &lists).unwrap(), r#"($."x" == "a\\b")"#);
+        assert_eq!(
+            list(r"\(a\)", &with_parentheses()).unwrap(),
+            r#"($."x" == "(a)")"#
+        );
+    }
+
+    #[test]
+    fn value_lists_ignore_trailing_separators() {
+        let lists = ValueLists::default();
+        assert_eq!(list("a_or_", &lists).unwrap(), r#"($."x" == "a")"#);
+        assert_eq!(list("a_and_", &lists).unwrap(), r#"($."x" == "a")"#);
+        assert_eq!(
+            list("a_or__or_b", &lists).unwrap(),
+            r#"(($."x" == "a") || ($."x" == "") || ($."x" == "b"))"#
+        );
+    }
+
+    #[test]
+    fn value_lists_reject_unbalanced_parentheses() {
+        let lists = with_parentheses();
+        assert!(matches!(
+            list("(a_or_b", &lists),
+            Err(CompassError::InvalidExpression(r)) if r == "missing ')' in value list"
+        ));
+        assert!(matches!(
+            list("a)_or_b", &lists),
+            Err(CompassError::InvalidExpression(r)) if r == "unbalanced parentheses in 'a)_or_b'"
+        ));
+    }
+
+    #[test]
+    fn value_lists_refuse_empty_separators() {
+        for lists in [
+            ValueLists {
+                and: String::new(),
+                ..ValueLists::default()
+            },
+            ValueLists {
+                or: String::new(),
+                ..ValueLists::default()
+            },
+        ] {
+            assert!(matches!(
+                list("a_or_b", &lists),
+                Err(CompassError::InvalidSchema(p)) if p[0].kind == SchemaProblemKind::InvalidSeparators
+            ));
+        }
+    }
+
+    #[test]
+    fn value_list_nesting_is_limited() {
+        let lists = with_parentheses();
+        let nested = |n: usize| format!("{}a{}", "(".repeat(n), ")".repeat(n));
+        assert!(list(&nested(MAX_NESTING), &lists).is_ok());
+        assert!(matches!(
+            list(&nested(MAX_NESTING + 1), &lists),
+            Err(CompassError::InvalidExpression(r)) if r == "value list nested more than 64 levels deep"
+        ));
+        assert!(list(&"(".repeat(3000), &lists).is_err());
+    }
+}
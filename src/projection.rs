@@ -0,0 +1,107 @@
+use super::*;
+
+use std::collections::BTreeMap;
+
+// the requested paths as a tree, so `c.d` and `c.e` end up in the same `c` object
+enum Projected {
+    Whole,
+    Keys(BTreeMap<String, Projected>),
+}
+
+impl Projected {
+    // asking for a whole object and for something inside it gets the whole object
+    fn insert(&mut self, path: &[String]) {
+        match (self, path) {
+            (Projected::Whole, _) => {}
+            (this, []) => *this = Projected::Whole,
+            (Projected::Keys(keys), [first, rest @ ..]) => keys
+                .entry(first.to_owned())
+                .or_insert_with(|| Projected::Keys(BTreeMap::new()))
+                .insert(rest),
+        }
+    }
+
+    fn to_sql(&self, path: &mut Vec<String>) -> String {
+        match self {
+            Projected::Whole => FieldPath(path.clone()).to_sql(),
+            Projected::Keys(keys) => {
+                let pairs: Vec<String> = keys
+                    .iter()
+                    .map(|(key, child)| {
+                        path.push(key.to_owned());
+                        let value = child.to_sql(path);
+                        path.pop();
+                        format!("{}, {}", escape::sql_literal(key), value)
+                    })
+                    .collect();
+                format!("jsonb_build_object({})", pairs.join(", "))
+            }
+        }
+    }
+}
+
+/// The expression selected instead of `object` for `fields=a,b,c.d`: an object with just those keys, nested paths staying nested. Every path has to be a returnable field, or inside a `Nested` one.
+pub(crate) fn projection_expr(schema: &Schema, spec: &str) -> Result<String, CompassError> {
+    let mut projected = Projected::Keys(BTreeMap::new());
+
+    for key in spec.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+        let path = FieldPath::new(key);
+        if !matches!(field_for_path(schema, &path), Some(f) if schema.returnable(f)) {
+            return Err(CompassError::FieldNotFound);
+        }
+        projected.insert(&path.0);
+    }
+
+    Ok(projected.to_sql(&mut Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn projection(spec: &str) -> Result<String, CompassError> {
+        let schema = Schema::from_yaml_str(
+            "table: t\ndefault_order_by: a\nfields:\n  a: {name: a, query: {type: NumericTag}}\n  c: {name: c, query: {type: Nested}}\n  hidden: {name: hidden, returnable: false, query: {type: NumericTag}}\n  \"it's\": {name: \"it's\", query: {type: Nested}}\n",
+        )
+        .unwrap();
+        projection_expr(&schema, spec)
+    }
+
+    #[test]
+    fn paths_into_the_same_object_are_merged() {
+        assert_eq!(
+            projection("a, c.d,c.e,").unwrap(),
+            "jsonb_build_object('a', (object #> '{\"a\"}'), 'c', jsonb_build_object('d', (object #> '{\"c\",\"d\"}'), 'e', (object #> '{\"c\",\"e\"}')))"
+        );
+    }
+
+    #[test]
+    fn whole_objects_absorb_paths_inside_them() {
+        let whole = "jsonb_build_object('c', (object #> '{\"c\"}'))";
+        assert_eq!(projection("c.d,c").unwrap(), whole);
+        assert_eq!(projection("c,c.d").unwrap(), whole);
+    }
+
+    #[test]
+    fn keys_are_escaped() {
+        assert_eq!(
+            projection("it's.x'y").unwrap(),
+            "jsonb_build_object('it''s', jsonb_build_object('x''y', (object #> '{\"it''s\",\"x''y\"}')))"
+        );
+        assert_eq!(
+            projection(r#"c.x"y\z"#).unwrap(),
+            r#"jsonb_build_object('c', jsonb_build_object(E'x"y\\z', (object #> E'{"c","x\\"y\\\\z"}')))"#
+        );
+    }
+
+    #[test]
+    fn only_returnable_fields_can_be_projected() {
+        for spec in ["nope", "a,nope", "a.b", "hidden", "c,hidden"] {
+            assert!(
+                matches!(projection(spec), Err(CompassError::FieldNotFound)),
+                "{:?} was accepted",
+                spec
+            );
+        }
+    }
+}
@@ -0,0 +1,626 @@
+use super::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A path into a document, one element per key. `["player", "name"]` is `$.player.name`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldPath(pub Vec<String>);
+
+impl FieldPath {
+    pub fn new(path: &str) -> FieldPath {
+        FieldPath(path.split('.').map(|x| x.to_owned()).collect())
+    }
+
+    /// Parses a sort key, either a dotted path or a postgres array literal like `{a,b}`.
+    pub fn from_sort_key(key: &str) -> FieldPath {
+        match key.strip_prefix('{').and_then(|k| k.strip_suffix('}')) {
+            Some(k) => FieldPath(k.split(',').map(|x| x.trim().to_owned()).collect()),
+            None => FieldPath::new(key),
+        }
+    }
+
+    /// Renders as `(object #> '{"a","b"}')`.
+    pub fn to_sql(&self) -> String {
+        format!("(object #> {})", escape::sql_text_array(&self.0))
+    }
+
+    /// Renders as `$."a"."b"`. Keys are always quoted, since nested paths come straight from the URL.
+    pub fn to_jsonpath(&self) -> String {
+        let mut out = String::from("$");
+        for key in &self.0 {
+            out.push('.');
+            out.push_str(&escape::jsonpath_string(key));
+        }
+        out
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join("."))
+    }
+}
+
+/// A decimal number, as digits and a scale rather than a float, so something like `0.1` ends up in JSONPath exactly as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    /// Rounds to `precision` decimal places, halves away from zero. Values with fewer places are left as they are.
+    pub fn round(&self, precision: u32) -> Option<Decimal> {
+        if self.scale <= precision {
+            return Some(*self);
+        }
+
+        let unit = 10i128.checked_pow(self.scale - precision)?;
+        let half = if self.mantissa < 0 {
+            -unit / 2
+        } else {
+            unit / 2
+        };
+        Some(Decimal {
+            mantissa: self.mantissa.checked_add(half)? / unit,
+            scale: precision,
+        })
+    }
+
+    /// The values that round to this one at `precision` decimal places: everything within half a unit of it, with the halfway points going to whichever side rounds away from zero.
+    /// If this has more decimal places than that, it's rounded first, so `3.57` at one place is everything that rounds to `3.6`.
+    pub fn rounding_range(&self, precision: u32) -> Option<RoundingRange> {
+        let rounded = self.round(precision)?;
+        let scale = precision + 1;
+        let mantissa = rounded
+            .mantissa
+            .checked_mul(10i128.checked_pow(scale - rounded.scale)?)?;
+
+        Some(RoundingRange {
+            low: Decimal {
+                mantissa: mantissa.checked_sub(5)?,
+                scale,
+            },
+            low_inclusive: mantissa > 0,
+            high: Decimal {
+                mantissa: mantissa.checked_add(5)?,
+                scale,
+            },
+            high_inclusive: mantissa < 0,
+        })
+    }
+}
+
+/// The values that round to some decimal, from [`Decimal::rounding_range`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundingRange {
+    pub low: Decimal,
+    pub low_inclusive: bool,
+    pub high: Decimal,
+    pub high_inclusive: bool,
+}
+
+impl From<i64> for Decimal {
+    fn from(n: i64) -> Decimal {
+        Decimal {
+            mantissa: n as i128,
+            scale: 0,
+        }
+    }
+}
+
+// plain digits with an optional sign and decimal point. no exponents, infinities or NaN, none of which mean anything as a filter
+impl FromStr for Decimal {
+    type Err = CompassError;
+
+    fn from_str(s: &str) -> Result<Decimal, CompassError> {
+        let err = || CompassError::InvalidDecimalError(s.to_owned());
+
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+        let digits = format!("{}{}", int, frac);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(err());
+        }
+
+        let mantissa = digits.parse::<i128>().map_err(|_| err())?;
+        Ok(Decimal {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: frac.len() as u32,
+        })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = self.scale as usize;
+        let digits = format!(
+            "{:0>width$}",
+            self.mantissa.unsigned_abs(),
+            width = scale + 1
+        );
+        let (int, frac) = digits.split_at(digits.len() - scale);
+
+        if self.mantissa < 0 {
+            write!(f, "-")?;
+        }
+        if frac.is_empty() {
+            write!(f, "{}", int)
+        } else {
+            write!(f, "{}.{}", int, frac)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Decimal(Decimal),
+    Bool(bool),
+    String(String),
+}
+
+impl Literal {
+    pub fn to_jsonpath(&self) -> String {
+        match self {
+            Literal::Int(n) => n.to_string(),
+            Literal::Decimal(d) => d.to_string(),
+            Literal::Bool(b) => b.to_string(),
+            Literal::String(s) => escape::jsonpath_string(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operator::Eq => write!(f, "=="),
+            Operator::Gt => write!(f, ">"),
+            Operator::Lt => write!(f, "<"),
+            Operator::Ge => write!(f, ">="),
+            Operator::Le => write!(f, "<="),
+        }
+    }
+}
+
+/// Intermediate representation of a search, built from URL parameters by [`Query::from_fields`].
+/// Everything but `Fulltext` can be expressed as JSONPath; `to_sql` handles the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Compare {
+        path: FieldPath,
+        op: Operator,
+        value: Literal,
+    },
+    Exists(FieldPath),
+    /// JSONPath's `starts with`, which is always case-sensitive
+    StartsWith {
+        path: FieldPath,
+        prefix: String,
+    },
+    /// JSONPath's `like_regex`. `flags` are its flags, like `i` for case-insensitive
+    Regex {
+        path: FieldPath,
+        pattern: String,
+        flags: String,
+    },
+    /// some element of the array at `path` equals one of `values`
+    AnyOf {
+        path: FieldPath,
+        values: Vec<Literal>,
+    },
+    /// compares the number of elements in the array at `path`
+    Length {
+        path: FieldPath,
+        op: Operator,
+        value: i64,
+    },
+    Fulltext {
+        targets: Vec<FulltextTarget>,
+        lang: String,
+        syntax: FulltextSyntax,
+        query: String,
+    },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    pub fn eq(path: &FieldPath, value: Literal) -> Query {
+        Query::Compare {
+            path: path.clone(),
+            op: Operator::Eq,
+            value,
+        }
+    }
+
+    /// Whether this query can be rendered as JSONPath, i.e. doesn't contain any full-text search.
+    pub fn is_jsonpath(&self) -> bool {
+        match self {
+            Query::Compare { .. }
+            | Query::Exists(_)
+            | Query::StartsWith { .. }
+            | Query::Regex { .. }
+            | Query::AnyOf { .. }
+            | Query::Length { .. } => true,
+            Query::Fulltext { .. } => false,
+            Query::And(qs) | Query::Or(qs) => qs.iter().all(Query::is_jsonpath),
+            Query::Not(q) => q.is_jsonpath(),
+        }
+    }
+
+    pub fn to_jsonpath(&self) -> Option<String> {
+        Some(match self {
+            Query::Compare { path, op, value } => {
+                format!("({} {} {})", path.to_jsonpath(), op, value.to_jsonpath())
+            }
+            Query::Exists(path) => format!("(exists({}))", path.to_jsonpath()),
+            Query::StartsWith { path, prefix } => format!(
+                "({} starts with {})",
+                path.to_jsonpath(),
+                escape::jsonpath_string(prefix)
+            ),
+            Query::Regex {
+                path,
+                pattern,
+                flags,
+            } if flags.is_empty() => format!(
+                "({} like_regex {})",
+                path.to_jsonpath(),
+                escape::jsonpath_string(pattern)
+            ),
+            Query::Regex {
+                path,
+                pattern,
+                flags,
+            } => format!(
+                "({} like_regex {} flag {})",
+                path.to_jsonpath(),
+                escape::jsonpath_string(pattern),
+                escape::jsonpath_string(flags)
+            ),
+            // a filter on the elements, rather than relying on lax mode unwrapping the array for ==
+            Query::AnyOf { path, values } => format!(
+                "(exists({}[*] ? ({})))",
+                path.to_jsonpath(),
+                values
+                    .iter()
+                    .map(|v| format!("@ == {}", v.to_jsonpath()))
+                    .collect::<Vec<String>>()
+                    .join(" || ")
+            ),
+            Query::Length { path, op, value } => {
+                format!("({}.size() {} {})", path.to_jsonpath(), op, value)
+            }
+            Query::Fulltext { .. } => return None,
+            Query::And(qs) => format!(
+                "({})",
+                qs.iter()
+                    .map(Query::to_jsonpath)
+                    .collect::<Option<Vec<String>>>()?
+                    .join(" && ")
+            ),
+            Query::Or(qs) => format!(
+                "({})",
+                qs.iter()
+                    .map(Query::to_jsonpath)
+                    .collect::<Option<Vec<String>>>()?
+                    .join(" || ")
+            ),
+            Query::Not(q) => format!("!({})", q.to_jsonpath()?),
+        })
+    }
+
+    /// Renders as a SQL boolean expression over the `object` column. Values are bound as text parameters, numbered from `bind_index`.
+    pub fn to_sql(
+        &self,
+        bindings: &mut Vec<String>,
+        bind_index: usize,
+    ) -> Result<String, CompassError> {
+        if let Some(json_query) = self.to_jsonpath() {
+            bindings.push(json_query);
+            return Ok(format!(
+                "object @@ CAST(${}::text AS JSONPATH)",
+                bindings.len() - 1 + bind_index
+            ));
+        }
+
+        Ok(match self {
+            Query::Fulltext {
+                targets,
+                lang,
+                syntax,
+                query,
+            } => {
+                bindings.push(query.to_owned());
+                format!(
+                    "{vector} @@ {function}({lang},${parameter})",
+                    vector = fulltext_vector(lang, targets)?,
+                    lang = escape::sql_literal(escape::sql_identifier(lang)?),
+                    function = syntax,
+                    parameter = bindings.len() - 1 + bind_index
+                )
+            }
+            Query::And(qs) => format!(
+                "({})",
+                qs.iter()
+                    .map(|q| q.to_sql(bindings, bind_index))
+                    .collect::<Result<Vec<String>, CompassError>>()?
+                    .join(" AND ")
+            ),
+            Query::Or(qs) => format!(
+                "({})",
+                qs.iter()
+                    .map(|q| q.to_sql(bindings, bind_index))
+                    .collect::<Result<Vec<String>, CompassError>>()?
+                    .join(" OR ")
+            ),
+            Query::Not(q) => format!("NOT ({})", q.to_sql(bindings, bind_index)?),
+            Query::Compare { .. }
+            | Query::Exists(_)
+            | Query::StartsWith { .. }
+            | Query::Regex { .. }
+            | Query::AnyOf { .. }
+            | Query::Length { .. } => unreachable!(),
+        })
+    }
+
+    /// Splits the top level of a query into the parts that can go into a single JSONPATH, and the parts that need SQL.
+    pub fn split_jsonpath(self) -> (Vec<Query>, Vec<Query>) {
+        match self {
+            Query::And(qs) => qs.into_iter().partition(Query::is_jsonpath),
+            q if q.is_jsonpath() => (vec![q], Vec::new()),
+            q => (Vec::new(), vec![q]),
+        }
+    }
+}
+
+/// What a full-text field named `name` searches: its `targets` if it has any, otherwise its `target` (or itself), unweighted.
+pub fn fulltext_targets(
+    name: &str,
+    target: Option<&str>,
+    targets: &[FulltextTarget],
+) -> Vec<FulltextTarget> {
+    if targets.is_empty() {
+        vec![FulltextTarget {
+            field: target.unwrap_or(name).to_owned(),
+            weight: None,
+        }]
+    } else {
+        targets.to_vec()
+    }
+}
+
+/// The tsvector a full-text field is searched against. `lang` and `targets` are spliced in rather than bound, so that this matches the index `generate_ddl` creates.
+pub fn fulltext_vector(lang: &str, targets: &[FulltextTarget]) -> Result<String, CompassError> {
+    let lang = escape::sql_literal(escape::sql_identifier(lang)?);
+
+    // a single unweighted target keeps the plain form, so indexes made before weights existed still get used
+    if let [FulltextTarget {
+        field,
+        weight: None,
+    }] = targets
+    {
+        return Ok(format!(
+            "to_tsvector({},object->>{})",
+            lang,
+            escape::sql_literal(field)
+        ));
+    }
+
+    // a missing target would otherwise null out the whole concatenation
+    let vectors: Vec<String> = targets
+        .iter()
+        .map(|t| {
+            let vector = format!(
+                "to_tsvector({},coalesce(object->>{},''))",
+                lang,
+                escape::sql_literal(&t.field)
+            );
+            match t.weight {
+                Some(weight) => format!("setweight({},'{}')", vector, weight),
+                None => vector,
+            }
+        })
+        .collect();
+
+    Ok(format!("({})", vectors.join(" || ")))
+}
+
+/// The tsquery a full-text search value turns into. Used for ranking and highlighting, where the value is spliced in escaped rather than bound.
+pub fn fulltext_query(
+    lang: &str,
+    syntax: FulltextSyntax,
+    query: &str,
+) -> Result<String, CompassError> {
+    Ok(format!(
+        "{}({},{})",
+        syntax,
+        escape::sql_literal(escape::sql_identifier(lang)?),
+        escape::sql_literal(query)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_eq(path: &str, value: &str) -> String {
+        Query::eq(&FieldPath::new(path), Literal::String(value.to_owned()))
+            .to_jsonpath()
+            .unwrap()
+    }
+
+    #[test]
+    fn values_are_escaped_in_jsonpath() {
+        assert_eq!(string_eq("name", "plain"), r#"($."name" == "plain")"#);
+        assert_eq!(
+            string_eq("name", r#"x" || $.secret == "y"#),
+            r#"($."name" == "x\" || $.secret == \"y")"#
+        );
+        assert_eq!(string_eq("name", r"a\"), r#"($."name" == "a\\")"#);
+        assert_eq!(
+            string_eq("name", "a\n\u{0}"),
+            r#"($."name" == "a\n\u0000")"#
+        );
+    }
+
+    #[test]
+    fn nested_path_keys_are_quoted() {
+        assert_eq!(FieldPath::new("meta.x").to_jsonpath(), r#"$."meta"."x""#);
+        assert_eq!(
+            FieldPath::new(r#"meta.a"b"#).to_jsonpath(),
+            r#"$."meta"."a\"b""#
+        );
+        assert_eq!(
+            FieldPath::new(r#"meta.x" || true || "#).to_jsonpath(),
+            r#"$."meta"."x\" || true || ""#
+        );
+        assert_eq!(
+            FieldPath::new(r"meta.a\").to_jsonpath(),
+            r#"$."meta"."a\\""#
+        );
+    }
+
+    #[test]
+    fn nested_path_keys_are_escaped_in_sql() {
+        assert_eq!(
+            FieldPath::new("meta.x").to_sql(),
+            r#"(object #> '{"meta","x"}')"#
+        );
+        assert_eq!(
+            FieldPath::new("meta.a'b").to_sql(),
+            r#"(object #> '{"meta","a''b"}')"#
+        );
+        assert_eq!(
+            FieldPath::new(r#"meta.a\"b"#).to_sql(),
+            r#"(object #> E'{"meta","a\\\\\\"b"}')"#
+        );
+    }
+
+    #[test]
+    fn jsonpath_queries_are_bound_not_spliced() {
+        let mut bindings = Vec::new();
+        let sql = Query::eq(&FieldPath::new("name"), Literal::String("'; --".to_owned()))
+            .to_sql(&mut bindings, 3)
+            .unwrap();
+        assert_eq!(sql, "object @@ CAST($3::text AS JSONPATH)");
+        assert_eq!(bindings, vec![r#"($."name" == "'; --")"#.to_owned()]);
+    }
+
+    #[test]
+    fn fulltext_targets_and_values_are_escaped() {
+        let targets = vec![FulltextTarget {
+            field: "it's".to_owned(),
+            weight: None,
+        }];
+        assert_eq!(
+            fulltext_vector("english", &targets).unwrap(),
+            "to_tsvector('english',object->>'it''s')"
+        );
+        assert_eq!(
+            fulltext_query("english", FulltextSyntax::Plain, r"a\' OR 1=1").unwrap(),
+            r"plainto_tsquery('english',E'a\\'' OR 1=1')"
+        );
+    }
+
+    #[test]
+    fn hostile_languages_are_rejected() {
+        let targets = fulltext_targets("body", None, &[]);
+        for lang in ["english'", "english); DROP TABLE t; --", "a.b.c"] {
+            assert!(matches!(
+                fulltext_vector(lang, &targets),
+                Err(CompassError::InvalidIdentifier(_))
+            ));
+            assert!(matches!(
+                fulltext_query(lang, FulltextSyntax::Plain, "x"),
+                Err(CompassError::InvalidIdentifier(_))
+            ));
+        }
+    }
+
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn decimals_parse_and_display() {
+        for (input, shown) in [
+            ("3.5", "3.5"),
+            ("-0.25", "-0.25"),
+            ("+7", "7"),
+            ("0.10", "0.10"),
+            ("-.5", "-0.5"),
+            ("5.", "5"),
+            ("007.50", "7.50"),
+        ] {
+            assert_eq!(decimal(input).to_string(), shown);
+        }
+
+        for input in [
+            "", "-", ".", "1e5", "NaN", "inf", "1.2.3", "--1", "1,5", " 1",
+        ] {
+            assert!(
+                matches!(input.parse::<Decimal>(), Err(CompassError::InvalidDecimalError(s)) if s == input),
+                "{:?} was accepted",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn decimals_round_half_away_from_zero() {
+        assert_eq!(decimal("3.57").round(1).unwrap().to_string(), "3.6");
+        assert_eq!(decimal("3.55").round(1).unwrap().to_string(), "3.6");
+        assert_eq!(decimal("3.54").round(1).unwrap().to_string(), "3.5");
+        assert_eq!(decimal("-3.55").round(1).unwrap().to_string(), "-3.6");
+        assert_eq!(decimal("2.5").round(0).unwrap().to_string(), "3");
+        assert_eq!(decimal("3.5").round(2).unwrap().to_string(), "3.5");
+    }
+
+    #[test]
+    fn rounding_ranges() {
+        let range = |s: &str, precision| {
+            let r = decimal(s).rounding_range(precision).unwrap();
+            format!(
+                "{}{}, {}{}",
+                if r.low_inclusive { "[" } else { "(" },
+                r.low,
+                r.high,
+                if r.high_inclusive { "]" } else { ")" }
+            )
+        };
+
+        assert_eq!(range("3.5", 1), "[3.45, 3.55)");
+        assert_eq!(range("3", 1), "[2.95, 3.05)");
+        assert_eq!(range("3", 0), "[2.5, 3.5)");
+        assert_eq!(range("3.57", 1), "[3.55, 3.65)");
+        assert_eq!(range("3.549", 1), "[3.45, 3.55)");
+        assert_eq!(range("3.55", 1), "[3.55, 3.65)");
+
+        // halves round away from zero, so below zero the ends swap
+        assert_eq!(range("-0.1", 1), "(-0.15, -0.05]");
+        assert_eq!(range("-3.55", 1), "(-3.65, -3.55]");
+        assert_eq!(range("-3.5", 0), "(-4.5, -3.5]");
+        assert_eq!(range("-3.549", 1), "(-3.55, -3.45]");
+        assert_eq!(range("0", 1), "(-0.05, 0.05)");
+        assert_eq!(range("-0.04", 1), "(-0.05, 0.05)");
+
+        assert_eq!(decimal("1").rounding_range(40), None);
+    }
+}
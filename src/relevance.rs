@@ -0,0 +1,138 @@
+use super::*;
+
+/// A full-text field that's being searched on in a request.
+pub(crate) struct FulltextMatch {
+    field: String,
+    lang: String,
+    targets: Vec<FulltextTarget>,
+    query: String,
+    rank: FulltextRank,
+}
+
+/// Finds the full-text searches in a request, in a stable order. Negated searches are left out, since there's nothing in a result that matched them.
+pub(crate) fn fulltext_matches(
+    schema: &Schema,
+    params: &SearchParams,
+) -> Result<Vec<FulltextMatch>, CompassError> {
+    let mut matches = Vec::new();
+    for (k, v) in &params.filters {
+        if let Some((
+            field,
+            FieldQuery::Fulltext {
+                lang,
+                syntax,
+                target,
+                targets,
+                rank,
+            },
+        )) = resolve_field(schema, k)
+        {
+            matches.push(FulltextMatch {
+                targets: fulltext_targets(&field, target.as_deref(), &targets),
+                field,
+                query: fulltext_query(&lang, syntax, v)?,
+                lang,
+                rank,
+            });
+        }
+    }
+
+    Ok(matches)
+}
+
+/// How well a row matches every full-text search in the request, for `sortby=relevance`.
+pub(crate) fn rank_expr(matches: &[FulltextMatch]) -> Result<Option<String>, CompassError> {
+    if matches.is_empty() {
+        return Ok(None);
+    }
+
+    let ranks = matches
+        .iter()
+        .map(|m| {
+            Ok(format!(
+                "{}({}, {})",
+                m.rank,
+                fulltext_vector(&m.lang, &m.targets)?,
+                m.query
+            ))
+        })
+        .collect::<Result<Vec<String>, CompassError>>()?;
+
+    Ok(Some(format!("({})", ranks.join(" + "))))
+}
+
+/// A jsonb object of field -> highlighted snippet, for every full-text search in the request.
+/// Snippets only come from targets the client could see anyway; a search with none of those gets no snippet.
+pub(crate) fn headlines_expr(
+    schema: &Schema,
+    matches: &[FulltextMatch],
+) -> Result<String, CompassError> {
+    let mut headlines = Vec::new();
+    for m in matches {
+        let targets: Vec<FulltextTarget> = m
+            .targets
+            .iter()
+            .filter(
+                |t| match field_for_path(schema, &FieldPath::new(&t.field)) {
+                    Some(f) => schema.returnable(f),
+                    None => true,
+                },
+            )
+            .cloned()
+            .collect();
+
+        if targets.is_empty() {
+            continue;
+        }
+
+        headlines.push(format!(
+            "{}, ts_headline({}, {}, {})",
+            escape::sql_literal(&m.field),
+            escape::sql_literal(escape::sql_identifier(&m.lang)?),
+            document(&targets),
+            m.query
+        ));
+    }
+
+    Ok(format!("jsonb_build_object({})", headlines.join(", ")))
+}
+
+// the text of all of a match's targets, for ts_headline to pick snippets out of
+fn document(targets: &[FulltextTarget]) -> String {
+    let texts: Vec<String> = targets
+        .iter()
+        .map(|t| format!("object->>{}", escape::sql_literal(&t.field)))
+        .collect();
+
+    if texts.len() == 1 {
+        texts[0].to_owned()
+    } else {
+        format!("concat_ws(' ', {})", texts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headlines_skip_unreturnable_targets() {
+        let schema = Schema::from_yaml_str(
+            "table: t\ndefault_order_by: title\nfields:\n  title: {name: title, query: {type: StringTag}}\n  notes: {name: notes, returnable: false, query: {type: StringTag}}\n  search: {name: search, query: {type: Fulltext, lang: english, targets: [{field: title}, {field: notes}, {field: body}]}}\n  secret: {name: secret, returnable: false, query: {type: Fulltext, lang: english}}\n",
+        )
+        .unwrap();
+        let params = SearchParams::parse("search=cat&secret=dog").unwrap();
+        let matches = fulltext_matches(&schema, &params).unwrap();
+        assert_eq!(matches.len(), 2);
+
+        assert_eq!(
+            headlines_expr(&schema, &matches).unwrap(),
+            "jsonb_build_object('search', ts_headline('english', concat_ws(' ', object->>'title', object->>'body'), websearch_to_tsquery('english','cat')))"
+        );
+
+        let internal = schema.with_visibility(Visibility::Internal);
+        let headlines = headlines_expr(&internal, &matches).unwrap();
+        assert!(headlines.contains("object->>'notes'"));
+        assert!(headlines.contains("'secret', ts_headline"));
+    }
+}
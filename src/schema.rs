@@ -8,6 +8,56 @@ pub struct Schema {
     pub fields: HashMap<String, Field>,
     pub default_order_by: String,
     pub table: String,
+    /// key that full-text highlights are added under in results, when asked for
+    #[serde(default = "default_highlight_key")]
+    pub highlight_key: String,
+    #[serde(default)]
+    pub value_lists: ValueLists,
+    /// which of the fields' visibility flags apply. not part of the YAML, since it depends on who's asking
+    #[serde(skip)]
+    pub visibility: Visibility,
+}
+
+/// Who a schema is being used for. `Public` respects each field's `hidden`, `filterable`, `sortable` and `returnable` flags; `Internal` ignores them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+    #[default]
+    Public,
+    Internal,
+}
+
+/// How several values are combined in one parameter, like `tag=a_or_b`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValueLists {
+    #[serde(default = "default_and")]
+    pub and: String,
+    #[serde(default = "default_or")]
+    pub or: String,
+    /// whether values can be grouped with parentheses, like `(a_or_b)_and_c`
+    #[serde(default)]
+    pub parentheses: bool,
+}
+
+impl default::Default for ValueLists {
+    fn default() -> Self {
+        ValueLists {
+            and: default_and(),
+            or: default_or(),
+            parentheses: false,
+        }
+    }
+}
+
+fn default_and() -> String {
+    "_and_".to_owned()
+}
+
+fn default_or() -> String {
+    "_or_".to_owned()
+}
+
+fn default_highlight_key() -> String {
+    "_highlights".to_owned()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -16,6 +66,19 @@ pub struct Field {
     pub converter: Option<ConverterSchema>,
     #[serde(default)]
     pub query: FieldQuery,
+    /// shorthand for turning off `filterable`, `sortable` and `returnable` all at once
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default = "default_true")]
+    pub filterable: bool,
+    #[serde(default = "default_true")]
+    pub sortable: bool,
+    #[serde(default = "default_true")]
+    pub returnable: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -32,22 +95,78 @@ pub enum FieldQuery {
         max: String,
         #[serde(default)]
         aliases: HashMap<String, i64>,
+        #[serde(default)]
+        number: NumberType,
+        /// for `Decimal` fields, how many decimal places equality is checked to
+        precision: Option<u32>,
+        #[serde(default)]
+        bounds: RangeBounds,
     },
     Fulltext {
         lang: String,
         #[serde(default)]
         syntax: FulltextSyntax,
         target: Option<String>,
+        /// several fields searched together as one document, instead of a single `target`
+        #[serde(default)]
+        targets: Vec<FulltextTarget>,
+        #[serde(default)]
+        rank: FulltextRank,
     },
     AmbiguousTag,
     NumericTag {
         #[serde(default)]
         aliases: HashMap<String, i64>,
+        #[serde(default)]
+        number: NumberType,
+        precision: Option<u32>,
+    },
+    StringTag {
+        /// values that stand for another one, looked up uppercased. e.g. `NYC: New York`
+        #[serde(default)]
+        aliases: HashMap<String, String>,
+        #[serde(default)]
+        case_insensitive: bool,
+        /// lets `e` match `é`, `è` and so on, for Latin letters
+        #[serde(default)]
+        accent_insensitive: bool,
+    },
+    /// matches part of a string, rather than all of it
+    Pattern {
+        mode: PatternMode,
+        #[serde(default)]
+        case_insensitive: bool,
     },
-    StringTag,
     Nested,
-    Min,
-    Max,
+    /// an array of values, like a converted tag list
+    Array {
+        #[serde(default)]
+        items: ArrayItems,
+        /// how plain values match, when they don't start with `any:`, `all:` or `none:`
+        #[serde(default)]
+        quantifier: Quantifier,
+        /// a parameter name for comparing the array's length, like a range
+        length: Option<String>,
+        /// whether `min..max` lengths include their ends, same as for a `Range`
+        #[serde(default)]
+        bounds: RangeBounds,
+    },
+    /// the `length` parameter of an `Array` field
+    ArrayLength {
+        bounds: RangeBounds,
+    },
+    Min {
+        #[serde(default)]
+        number: NumberType,
+        #[serde(default)]
+        inclusive: bool,
+    },
+    Max {
+        #[serde(default)]
+        number: NumberType,
+        #[serde(default)]
+        inclusive: bool,
+    },
     Bool,
     Not(Box<FieldQuery>),
 }
@@ -58,7 +177,62 @@ impl default::Default for FieldQuery {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+/// Whether a range's min and max parameters include the value they're given. Exclusive unless said otherwise, so `season_min=16` means after season 16.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RangeBounds {
+    #[default]
+    Exclusive,
+    Inclusive,
+    MinInclusive,
+    MaxInclusive,
+}
+
+impl RangeBounds {
+    pub fn min_inclusive(&self) -> bool {
+        matches!(self, RangeBounds::Inclusive | RangeBounds::MinInclusive)
+    }
+
+    pub fn max_inclusive(&self) -> bool {
+        matches!(self, RangeBounds::Inclusive | RangeBounds::MaxInclusive)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternMode {
+    Prefix,
+    Substring,
+    /// the value is a regular expression, in postgres' `like_regex` flavor
+    Regex,
+}
+
+/// What an array field's elements are, i.e. how values to compare them to are parsed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayItems {
+    #[default]
+    String,
+    Number,
+    /// numbers or strings, like `AmbiguousTag`
+    Ambiguous,
+}
+
+/// How many of the values in an array filter have to be in the array.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantifier {
+    #[default]
+    Any,
+    All,
+    None,
+}
+
+/// What kind of numbers a numeric field holds, i.e. how its URL parameters are parsed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberType {
+    #[default]
+    Integer,
+    Decimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FulltextSyntax {
     TsQuery,
     Plain,
@@ -83,6 +257,48 @@ impl fmt::Display for FulltextSyntax {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FulltextRank {
+    #[default]
+    Rank,
+    CoverDensity,
+}
+
+impl fmt::Display for FulltextRank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FulltextRank::Rank => write!(f, "ts_rank"),
+            FulltextRank::CoverDensity => write!(f, "ts_rank_cd"),
+        }
+    }
+}
+
+/// One of the fields a full-text field searches. Matches in higher weighted targets rank higher, A being the highest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FulltextTarget {
+    pub field: String,
+    pub weight: Option<FulltextWeight>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FulltextWeight {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl fmt::Display for FulltextWeight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FulltextWeight::A => write!(f, "A"),
+            FulltextWeight::B => write!(f, "B"),
+            FulltextWeight::C => write!(f, "C"),
+            FulltextWeight::D => write!(f, "D"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum ConvertFrom {
     CommaSeparatedString,
@@ -115,3 +331,20 @@ impl<'r> FromRequest<'r> for Schema {
             .map(|s| s.inner().clone()) // clone bad, i know
     }
 }
+
+/// A request guard for the managed `Schema` with `Visibility::Internal`, for routes that should see every field. Taking `Schema` itself gets `Visibility::Public`.
+#[cfg(feature = "rocket_support")]
+pub struct InternalSchema(pub Schema);
+
+#[cfg(feature = "rocket_support")]
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for InternalSchema {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request
+            .guard::<&State<Schema>>()
+            .await
+            .map(|s| InternalSchema(s.inner().with_visibility(Visibility::Internal)))
+    }
+}
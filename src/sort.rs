@@ -0,0 +1,549 @@
+use super::*;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+use relevance::{fulltext_matches, rank_expr};
+
+use uuid::Uuid;
+
+/// What values of a sort key are compared as. Comparing as jsonb sorts any mix of types, but not always in a sensible order: `"10"` comes before `9`, and every string after every number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortType {
+    Jsonb,
+    Text,
+    Numeric,
+    BigInt,
+    /// full-text search rank, from `sortby=relevance`
+    Relevance,
+}
+
+impl SortType {
+    /// Picks how a field should be sorted, from how it's queried and converted.
+    pub fn of(field: &Field) -> SortType {
+        match field.converter {
+            Some(ConverterSchema {
+                to: ConvertTo::Timestamp,
+                ..
+            })
+            | Some(ConverterSchema {
+                to: ConvertTo::TimestampMillis,
+                ..
+            }) => SortType::BigInt,
+            Some(ConverterSchema {
+                to: ConvertTo::TagArray,
+                ..
+            }) => SortType::Jsonb,
+            None => SortType::of_query(&field.query),
+        }
+    }
+
+    fn of_query(query: &FieldQuery) -> SortType {
+        match query {
+            FieldQuery::Range { .. }
+            | FieldQuery::Min { .. }
+            | FieldQuery::Max { .. }
+            | FieldQuery::NumericTag { .. }
+            | FieldQuery::ArrayLength { .. } => SortType::Numeric,
+            FieldQuery::StringTag { .. }
+            | FieldQuery::Pattern { .. }
+            | FieldQuery::Fulltext { .. } => SortType::Text,
+            FieldQuery::Not(inner) => SortType::of_query(inner),
+            FieldQuery::AmbiguousTag
+            | FieldQuery::Nested
+            | FieldQuery::Bool
+            | FieldQuery::Array { .. } => SortType::Jsonb,
+        }
+    }
+
+    // values that don't look like the right type sort as nulls, rather than making the cast fail the whole query
+    pub(crate) fn expr(&self, path: &FieldPath) -> String {
+        let text = format!("(object #>> {})", escape::sql_text_array(&path.0));
+        match self {
+            SortType::Jsonb => path.to_sql(),
+            SortType::Text => text,
+            SortType::Numeric => format!(
+                "(CASE WHEN {text} ~ '^-?[0-9]+(\\.[0-9]+)?$' THEN {text}::numeric END)",
+                text = text
+            ),
+            SortType::BigInt => format!(
+                "(CASE WHEN {text} ~ '^-?[0-9]+$' THEN {text}::bigint END)",
+                text = text
+            ),
+            // relevance keys are built with their expression, see SortKey::relevance
+            SortType::Relevance => unreachable!(),
+        }
+    }
+
+    fn param(&self, index: usize) -> String {
+        match self {
+            SortType::Jsonb => format!("CAST(${}::text AS JSONB)", index),
+            SortType::Text => format!("${}::text", index),
+            SortType::Numeric => format!("CAST(${}::text AS NUMERIC)", index),
+            SortType::BigInt => format!("CAST(${}::text AS BIGINT)", index),
+            SortType::Relevance => format!("CAST(${}::text AS REAL)", index),
+        }
+    }
+
+    fn binding(&self, value: &Value) -> String {
+        match (self, value) {
+            (SortType::Jsonb, _) => value.to_string(),
+            (_, Value::String(s)) => s.to_owned(),
+            _ => value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub path: FieldPath,
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub sort_type: SortType,
+    expr: String,
+}
+
+impl SortKey {
+    /// Parses one key of a `sortby` list: `[+|-]path[:nullsfirst|:nullslast]`.
+    /// Keys without a `+` or `-` sort descending if `default_descending` is set. Nulls go where postgres puts them by default, i.e. first when descending.
+    /// `relevance` sorts by `rank`, the full-text rank expression, and is only allowed when there is one.
+    pub fn parse(
+        schema: &Schema,
+        key: &str,
+        default_descending: bool,
+        rank: Option<&str>,
+    ) -> Result<SortKey, CompassError> {
+        let (key, nulls_first) = if let Some(k) = key.strip_suffix(":nullsfirst") {
+            (k, Some(true))
+        } else if let Some(k) = key.strip_suffix(":nullslast") {
+            (k, Some(false))
+        } else {
+            (key, None)
+        };
+
+        let (key, descending) = if let Some(k) = key.strip_prefix('-') {
+            (k, true)
+        } else if let Some(k) = key.strip_prefix('+') {
+            (k, false)
+        } else {
+            (key, default_descending)
+        };
+
+        if key.trim() == "relevance" {
+            return Ok(SortKey {
+                path: FieldPath(vec!["relevance".to_owned()]),
+                descending,
+                nulls_first: nulls_first.unwrap_or(descending),
+                sort_type: SortType::Relevance,
+                expr: rank.ok_or(CompassError::FieldNotFound)?.to_owned(),
+            });
+        }
+
+        let path = FieldPath::from_sort_key(key.trim());
+        let sort_type = match field_for_path(schema, &path) {
+            // paths inside nested fields can hold anything
+            Some(field) if path.0.len() == 1 => SortType::of(field),
+            Some(_) => SortType::Jsonb,
+            None => return Err(CompassError::FieldNotFound),
+        };
+
+        Ok(SortKey {
+            expr: sort_type.expr(&path),
+            path,
+            descending,
+            nulls_first: nulls_first.unwrap_or(descending),
+            sort_type,
+        })
+    }
+
+    /// The SQL expression rows are ordered by. This is also the expression sort indexes are created on.
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    fn nulls_first(&self) -> bool {
+        self.nulls_first
+    }
+
+    /// A condition matching rows that come strictly after `value` under this key.
+    fn after(&self, value: &Value, bindings: &mut Vec<String>, bind_index: usize) -> String {
+        if value.is_null() {
+            return if self.nulls_first() {
+                format!("{} IS NOT NULL", self.expr())
+            } else {
+                "FALSE".to_owned()
+            };
+        }
+
+        bindings.push(self.sort_type.binding(value));
+        let compare = format!(
+            "{} {} {}",
+            self.expr(),
+            if self.descending { "<" } else { ">" },
+            self.sort_type.param(bindings.len() - 1 + bind_index)
+        );
+
+        if self.nulls_first() {
+            compare
+        } else {
+            format!("({} OR {} IS NULL)", compare, self.expr())
+        }
+    }
+
+    fn equals(&self, value: &Value, bindings: &mut Vec<String>, bind_index: usize) -> String {
+        if value.is_null() {
+            return format!("{} IS NULL", self.expr());
+        }
+
+        bindings.push(self.sort_type.binding(value));
+        format!(
+            "{} = {}",
+            self.expr(),
+            self.sort_type.param(bindings.len() - 1 + bind_index)
+        )
+    }
+}
+
+/// How search results are ordered. Ties are always broken by `doc_id`, so the order is total and cursors can point at an exact row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort {
+    pub keys: Vec<SortKey>,
+}
+
+impl Sort {
+    pub fn from_fields(
+        schema: &Schema,
+        fields: &HashMap<String, String>,
+    ) -> Result<Sort, CompassError> {
+        Sort::from_params(schema, &SearchParams::from_fields(fields)?)
+    }
+
+    pub fn from_params(schema: &Schema, params: &SearchParams) -> Result<Sort, CompassError> {
+        let descending = match params.sortorder {
+            Some(ref l) => l.as_str().to_uppercase() == "DESC",
+            None => true,
+        };
+
+        let sort_by = match params.sortby {
+            Some(ref l) => l.as_str(),
+            None => schema.default_order_by.as_str(),
+        };
+
+        let rank = rank_expr(&fulltext_matches(schema, params)?)?;
+
+        let sort = Sort::parse(schema, sort_by, descending, rank.as_deref())?;
+
+        // the default order is up to the schema, so `sortable` only limits keys from the request.
+        // every key's values end up in cursors, though, which clients can decode
+        for key in &sort.keys {
+            if key.sort_type == SortType::Relevance {
+                continue;
+            }
+
+            let visible = match field_for_path(schema, &key.path) {
+                Some(f) => schema.returnable(f) && (params.sortby.is_none() || schema.sortable(f)),
+                None => false,
+            };
+            if !visible {
+                return Err(CompassError::FieldNotFound);
+            }
+        }
+
+        Ok(sort)
+    }
+
+    /// Parses a comma-separated list of sort keys, like `season,-day,name:nullsfirst`.
+    pub fn parse(
+        schema: &Schema,
+        spec: &str,
+        default_descending: bool,
+        rank: Option<&str>,
+    ) -> Result<Sort, CompassError> {
+        // a single postgres array literal, like {player,name}
+        if spec.starts_with('{') && spec.ends_with('}') {
+            return Ok(Sort {
+                keys: vec![SortKey::parse(schema, spec, default_descending, rank)?],
+            });
+        }
+
+        Ok(Sort {
+            keys: spec
+                .split(',')
+                .filter(|k| !k.trim().is_empty())
+                .map(|k| SortKey::parse(schema, k, default_descending, rank))
+                .collect::<Result<Vec<SortKey>, CompassError>>()?,
+        })
+    }
+
+    pub fn order_by(&self) -> String {
+        let mut keys: Vec<String> = self
+            .keys
+            .iter()
+            .map(|k| {
+                format!(
+                    "{} {} {}",
+                    k.expr(),
+                    if k.descending { "DESC" } else { "ASC" },
+                    if k.nulls_first {
+                        "NULLS FIRST"
+                    } else {
+                        "NULLS LAST"
+                    }
+                )
+            })
+            .collect();
+        keys.push("doc_id NULLS LAST".to_owned());
+        keys.join(", ")
+    }
+
+    /// The sort values of a row, as a jsonb array. Selected alongside results to build cursors from.
+    pub fn values(&self) -> String {
+        format!(
+            "jsonb_build_array({})",
+            self.keys
+                .iter()
+                .map(SortKey::expr)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )
+    }
+
+    /// Identifies this ordering, so cursors can't be reused under a different one.
+    fn signature(&self) -> String {
+        self.keys
+            .iter()
+            .map(|k| {
+                format!(
+                    "{}{}:{}",
+                    if k.descending { "-" } else { "+" },
+                    k.path,
+                    if k.nulls_first {
+                        "nullsfirst"
+                    } else {
+                        "nullslast"
+                    }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    /// A condition matching every row after the one `cursor` points at.
+    pub fn after(
+        &self,
+        cursor: &Cursor,
+        bindings: &mut Vec<String>,
+        bind_index: usize,
+    ) -> Result<String, CompassError> {
+        if cursor.sort != self.signature() || cursor.values.len() != self.keys.len() {
+            return Err(CompassError::InvalidCursor);
+        }
+
+        // (k1 > v1) OR (k1 = v1 AND k2 > v2) OR ... OR (k1 = v1 AND ... AND doc_id > id)
+        let mut alternatives = Vec::new();
+        let mut equal_so_far: Vec<String> = Vec::new();
+
+        for (key, value) in self.keys.iter().zip(cursor.values.iter()) {
+            let mut alternative = equal_so_far.clone();
+            alternative.push(key.after(value, bindings, bind_index));
+            alternatives.push(format!("({})", alternative.join(" AND ")));
+            equal_so_far.push(key.equals(value, bindings, bind_index));
+        }
+
+        bindings.push(cursor.doc_id.to_string());
+        equal_so_far.push(format!(
+            "doc_id > CAST(${}::text AS UUID)",
+            bindings.len() - 1 + bind_index
+        ));
+        alternatives.push(format!("({})", equal_so_far.join(" AND ")));
+
+        Ok(format!("({})", alternatives.join(" OR ")))
+    }
+
+    pub fn cursor(&self, values: Value, doc_id: Uuid) -> Cursor {
+        Cursor {
+            sort: self.signature(),
+            values: match values {
+                Value::Array(v) => v,
+                v => vec![v],
+            },
+            doc_id,
+        }
+    }
+}
+
+/// Points at the last row of a page. Handed to clients as an opaque string through `encode`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cursor {
+    sort: String,
+    values: Vec<Value>,
+    doc_id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        base64::encode_config(serde_json::to_vec(self).unwrap(), base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(s: &str) -> Result<Cursor, CompassError> {
+        let bytes = base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| CompassError::InvalidCursor)?;
+        serde_json::from_slice(&bytes).map_err(|_| CompassError::InvalidCursor)
+    }
+}
+
+/// Finds the field a path points at: either the field itself, or a nested field the path is inside of.
+pub fn field_for_path<'a>(schema: &'a Schema, path: &FieldPath) -> Option<&'a Field> {
+    if let Some(field) = schema.fields.get(&path.to_string()) {
+        return Some(field);
+    }
+
+    match path.0.first().and_then(|f| schema.fields.get(f)) {
+        Some(field) if path.0.len() == 1 || matches!(field.query, FieldQuery::Nested) => {
+            Some(field)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    const NAME: &str = r#"(object #>> '{"name"}')"#;
+    const TEAM: &str = r#"(object #>> '{"team"}')"#;
+
+    fn schema() -> Schema {
+        Schema::from_yaml_str(
+            "table: t\ndefault_order_by: name\nfields:\n  name: {name: name, query: {type: StringTag}}\n  team: {name: team, query: {type: StringTag}}\n",
+        )
+        .unwrap()
+    }
+
+    fn after(spec: &str, values: Value) -> (String, Vec<String>) {
+        let sort = Sort::parse(&schema(), spec, true, None).unwrap();
+        let cursor = sort.cursor(values, Uuid::nil());
+        let mut bindings = Vec::new();
+        let sql = sort.after(&cursor, &mut bindings, 4).unwrap();
+        (sql, bindings)
+    }
+
+    fn with_keys(sql: &str) -> String {
+        sql.replace("NAME", NAME).replace("TEAM", TEAM)
+    }
+
+    #[test]
+    fn after_depends_on_direction_and_null_placement() {
+        for (spec, condition) in [
+            ("+name", "((NAME > $4::text OR NAME IS NULL))"),
+            ("-name", "(NAME < $4::text)"),
+            ("+name:nullsfirst", "(NAME > $4::text)"),
+            ("-name:nullslast", "((NAME < $4::text OR NAME IS NULL))"),
+        ] {
+            let (sql, bindings) = after(spec, json!(["a"]));
+            assert_eq!(
+                sql,
+                with_keys(&format!(
+                    "({} OR (NAME = $5::text AND doc_id > CAST($6::text AS UUID)))",
+                    condition
+                )),
+                "for {:?}",
+                spec
+            );
+            assert_eq!(bindings, vec!["a", "a", &Uuid::nil().to_string()]);
+        }
+    }
+
+    #[test]
+    fn after_a_null_value() {
+        // with nulls last, nothing but other nulls comes after a null
+        let (sql, bindings) = after("+name", json!([null]));
+        assert_eq!(
+            sql,
+            with_keys("((FALSE) OR (NAME IS NULL AND doc_id > CAST($4::text AS UUID)))")
+        );
+        assert_eq!(bindings, vec![Uuid::nil().to_string()]);
+
+        let (sql, _) = after("-name", json!([null]));
+        assert_eq!(
+            sql,
+            with_keys("((NAME IS NOT NULL) OR (NAME IS NULL AND doc_id > CAST($4::text AS UUID)))")
+        );
+    }
+
+    #[test]
+    fn after_breaks_ties_key_by_key_then_on_doc_id() {
+        let (sql, bindings) = after("-name,+team", json!(["a", "b"]));
+        assert_eq!(
+            sql,
+            with_keys(
+                "((NAME < $4::text) \
+                 OR (NAME = $5::text AND (TEAM > $6::text OR TEAM IS NULL)) \
+                 OR (NAME = $5::text AND TEAM = $7::text AND doc_id > CAST($8::text AS UUID)))"
+            )
+        );
+        assert_eq!(bindings, vec!["a", "a", "b", "b", &Uuid::nil().to_string()]);
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let sort = Sort::parse(&schema(), "-name,+team", true, None).unwrap();
+        let cursor = sort.cursor(json!(["a", null]), Uuid::nil());
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn cursors_only_work_with_their_own_order() {
+        let sort = Sort::parse(&schema(), "-name", true, None).unwrap();
+        let mut bindings = Vec::new();
+
+        for other in ["+name", "-name:nullslast", "-team", "-name,+team"] {
+            let cursor = Sort::parse(&schema(), other, true, None)
+                .unwrap()
+                .cursor(json!(["a", "b"]), Uuid::nil());
+            assert!(
+                matches!(
+                    sort.after(&cursor, &mut bindings, 4),
+                    Err(CompassError::InvalidCursor)
+                ),
+                "cursor for {:?} was accepted",
+                other
+            );
+        }
+
+        // the right signature, but values that don't fit it
+        let mut cursor = sort.cursor(json!(["a"]), Uuid::nil());
+        cursor.values.push(json!("b"));
+        assert!(matches!(
+            sort.after(&cursor, &mut bindings, 4),
+            Err(CompassError::InvalidCursor)
+        ));
+    }
+
+    #[test]
+    fn garbage_cursors_are_invalid() {
+        let valid = Sort::parse(&schema(), "-name", true, None)
+            .unwrap()
+            .cursor(json!(["a"]), Uuid::nil())
+            .encode();
+
+        for garbage in [
+            "",
+            "not base64!",
+            "bm90IGpzb24",
+            "eyJzb3J0IjoiLW5hbWUifQ",
+            &valid[..valid.len() - 4],
+            &format!("{}=", valid),
+        ] {
+            assert!(
+                matches!(Cursor::decode(garbage), Err(CompassError::InvalidCursor)),
+                "{:?} was accepted",
+                garbage
+            );
+        }
+    }
+}
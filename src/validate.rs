@@ -0,0 +1,413 @@
+use super::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// URL parameters that compass handles itself, which fields can't be named after.
+pub const RESERVED_PARAMETERS: &[&str] = &[
+    "sortby",
+    "sortorder",
+    "limit",
+    "offset",
+    "cursor",
+    "highlight",
+    "q",
+    "fields",
+];
+
+/// The most decimal places a field's `precision` can be. Comfortably more than any real data has, and little enough that rounding ranges never overflow on it.
+pub const MAX_PRECISION: u32 = 18;
+
+/// Text search configurations that ship with PostgreSQL.
+pub const FULLTEXT_LANGUAGES: &[&str] = &[
+    "simple",
+    "arabic",
+    "armenian",
+    "basque",
+    "catalan",
+    "danish",
+    "dutch",
+    "english",
+    "finnish",
+    "french",
+    "german",
+    "greek",
+    "hindi",
+    "hungarian",
+    "indonesian",
+    "irish",
+    "italian",
+    "lithuanian",
+    "nepali",
+    "norwegian",
+    "portuguese",
+    "romanian",
+    "russian",
+    "serbian",
+    "spanish",
+    "swedish",
+    "tamil",
+    "turkish",
+    "yiddish",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaProblemKind {
+    /// a name this field answers to (itself, or a range's min/max) is already taken by another field
+    NameCollision(String),
+    /// a name this field answers to is one of [`RESERVED_PARAMETERS`]
+    ReservedName(String),
+    /// nested fields are matched on the part before the first dot, so they can't have one themselves
+    DottedNestedField,
+    UnsupportedLanguage(String),
+    NegatedFulltext,
+    /// `precision` is set on an `Integer` field
+    IntegerPrecision,
+    /// `precision` is more than [`MAX_PRECISION`]
+    ExcessivePrecision(u32),
+    /// a full-text field has both `target` and `targets`
+    ConflictingTargets,
+    EmptyOrderBy,
+    /// default_order_by refers to something that isn't a field
+    UnknownSortField(String),
+    /// default_order_by sorts on a field that isn't returnable, whose values would end up in cursors
+    HiddenSortField(String),
+    InvalidTable(String),
+    /// the `value_lists` separators are empty, the same, or a parenthesis when those group values
+    InvalidSeparators,
+    /// a `value_lists` separator has a comma in it, which `Array` fields already use between elements
+    CommaSeparator,
+}
+
+/// Something wrong with a schema. `field` is the offending field, if there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaProblem {
+    pub field: Option<String>,
+    pub kind: SchemaProblemKind,
+}
+
+impl fmt::Display for SchemaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref field) = self.field {
+            write!(f, "field '{}': ", field)?;
+        }
+
+        match self.kind {
+            SchemaProblemKind::NameCollision(ref name) => {
+                write!(f, "'{}' is already used by another field", name)
+            }
+            SchemaProblemKind::ReservedName(ref name) => {
+                write!(f, "'{}' is a reserved parameter name", name)
+            }
+            SchemaProblemKind::DottedNestedField => {
+                write!(f, "nested field names can't contain '.'")
+            }
+            SchemaProblemKind::UnsupportedLanguage(ref lang) => {
+                write!(f, "'{}' is not a supported full-text language", lang)
+            }
+            SchemaProblemKind::NegatedFulltext => {
+                write!(f, "full-text queries can't be wrapped in Not")
+            }
+            SchemaProblemKind::IntegerPrecision => {
+                write!(f, "precision only applies to Decimal fields")
+            }
+            SchemaProblemKind::ExcessivePrecision(precision) => write!(
+                f,
+                "precision {} is more than the maximum of {}",
+                precision, MAX_PRECISION
+            ),
+            SchemaProblemKind::ConflictingTargets => {
+                write!(f, "full-text fields can have a target or targets, not both")
+            }
+            SchemaProblemKind::EmptyOrderBy => write!(f, "default_order_by is empty"),
+            SchemaProblemKind::UnknownSortField(ref order) => write!(
+                f,
+                "default_order_by '{}' refers to a field not in the schema",
+                order
+            ),
+            SchemaProblemKind::HiddenSortField(ref order) => write!(
+                f,
+                "default_order_by '{}' sorts on a field that isn't returnable",
+                order
+            ),
+            SchemaProblemKind::InvalidTable(ref table) => {
+                write!(f, "'{}' is not a valid table name", table)
+            }
+            SchemaProblemKind::InvalidSeparators => write!(
+                f,
+                "value_lists separators must be different, non-empty and not parentheses"
+            ),
+            SchemaProblemKind::CommaSeparator => write!(
+                f,
+                "array elements are separated by ',', so value_lists separators can't contain it"
+            ),
+        }
+    }
+}
+
+impl Schema {
+    /// Parses a schema from YAML, and validates it.
+    pub fn from_yaml_str(s: &str) -> Result<Schema, CompassError> {
+        let schema: Schema = serde_yaml::from_str(s)?;
+        schema.validate().map_err(CompassError::InvalidSchema)?;
+        Ok(schema)
+    }
+
+    /// Reads a YAML schema from a file, and validates it.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Schema, CompassError> {
+        Schema::from_yaml_str(&std::fs::read_to_string(path)?)
+    }
+
+    /// Checks for mistakes that deserializing alone can't catch, which would otherwise only show up at query time.
+    pub fn validate(&self) -> Result<(), Vec<SchemaProblem>> {
+        let mut problems = Vec::new();
+
+        if escape::sql_identifier(&self.table).is_err() {
+            problems.push(SchemaProblem {
+                field: None,
+                kind: SchemaProblemKind::InvalidTable(self.table.to_owned()),
+            });
+        }
+
+        if self.default_order_by.trim().is_empty() {
+            problems.push(SchemaProblem {
+                field: None,
+                kind: SchemaProblemKind::EmptyOrderBy,
+            });
+        } else {
+            match Sort::parse(self, &self.default_order_by, true, None) {
+                Ok(sort) => {
+                    let hidden = sort.keys.iter().any(|k| {
+                        matches!(field_for_path(self, &k.path), Some(f) if f.hidden || !f.returnable)
+                    });
+                    if hidden {
+                        problems.push(SchemaProblem {
+                            field: None,
+                            kind: SchemaProblemKind::HiddenSortField(
+                                self.default_order_by.to_owned(),
+                            ),
+                        });
+                    }
+                }
+                Err(_) => problems.push(SchemaProblem {
+                    field: None,
+                    kind: SchemaProblemKind::UnknownSortField(self.default_order_by.to_owned()),
+                }),
+            }
+        }
+
+        let lists = &self.value_lists;
+        let comma_separated = lists.and.contains(',') || lists.or.contains(',');
+        let grouping = |s: &str| lists.parentheses && (s.contains('(') || s.contains(')'));
+        if lists.and.is_empty()
+            || lists.or.is_empty()
+            || lists.and.starts_with(lists.or.as_str())
+            || lists.or.starts_with(lists.and.as_str())
+            || grouping(&lists.and)
+            || grouping(&lists.or)
+        {
+            problems.push(SchemaProblem {
+                field: None,
+                kind: SchemaProblemKind::InvalidSeparators,
+            });
+        }
+
+        // sorted so problems always come out in the same order
+        let mut names: Vec<&String> = self.fields.keys().collect();
+        names.sort();
+
+        let mut taken: HashMap<&str, &str> = HashMap::new();
+        for name in names.iter() {
+            taken.insert(name, name);
+        }
+
+        for name in names {
+            let field = &self.fields[name];
+            let mut problem = |kind| {
+                problems.push(SchemaProblem {
+                    field: Some(name.to_owned()),
+                    kind,
+                })
+            };
+
+            if comma_separated && is_array(&field.query) {
+                problem(SchemaProblemKind::CommaSeparator);
+            }
+
+            let mut aliases = vec![name.as_str()];
+            match field.query {
+                FieldQuery::Range {
+                    ref min, ref max, ..
+                } => {
+                    if min == max {
+                        problem(SchemaProblemKind::NameCollision(min.to_owned()));
+                    }
+                    aliases.push(min);
+                    aliases.push(max);
+                }
+                FieldQuery::Array {
+                    length: Some(ref length),
+                    ..
+                } => {
+                    if length == name {
+                        problem(SchemaProblemKind::NameCollision(length.to_owned()));
+                    }
+                    aliases.push(length);
+                }
+                FieldQuery::Nested if name.contains('.') => {
+                    problem(SchemaProblemKind::DottedNestedField)
+                }
+                _ => {}
+            }
+
+            for alias in aliases {
+                if RESERVED_PARAMETERS.contains(&alias) {
+                    problem(SchemaProblemKind::ReservedName(alias.to_owned()));
+                }
+
+                if alias != name {
+                    match taken.get(alias) {
+                        Some(owner) if owner != name => {
+                            problem(SchemaProblemKind::NameCollision(alias.to_owned()))
+                        }
+                        _ => {
+                            taken.insert(alias, name);
+                        }
+                    }
+                }
+            }
+
+            check_query(&field.query, false, &mut problem);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+fn is_array(query: &FieldQuery) -> bool {
+    match query {
+        FieldQuery::Array { .. } => true,
+        FieldQuery::Not(inner) => is_array(inner),
+        _ => false,
+    }
+}
+
+fn check_query<F>(query: &FieldQuery, negated: bool, problem: &mut F)
+where
+    F: FnMut(SchemaProblemKind),
+{
+    match query {
+        FieldQuery::Fulltext {
+            ref lang,
+            ref target,
+            ref targets,
+            ..
+        } => {
+            if negated {
+                problem(SchemaProblemKind::NegatedFulltext);
+            }
+
+            if target.is_some() && !targets.is_empty() {
+                problem(SchemaProblemKind::ConflictingTargets);
+            }
+
+            let unqualified = lang.strip_prefix("pg_catalog.").unwrap_or(lang);
+            if !FULLTEXT_LANGUAGES.contains(&unqualified) {
+                problem(SchemaProblemKind::UnsupportedLanguage(lang.to_owned()));
+            }
+        }
+        FieldQuery::Range {
+            number: NumberType::Integer,
+            precision: Some(_),
+            ..
+        }
+        | FieldQuery::NumericTag {
+            number: NumberType::Integer,
+            precision: Some(_),
+            ..
+        } => problem(SchemaProblemKind::IntegerPrecision),
+        FieldQuery::Range {
+            precision: Some(precision),
+            ..
+        }
+        | FieldQuery::NumericTag {
+            precision: Some(precision),
+            ..
+        } if *precision > MAX_PRECISION => {
+            problem(SchemaProblemKind::ExcessivePrecision(*precision))
+        }
+        FieldQuery::Not(inner) => check_query(inner, true, problem),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &str =
+        "table: t\ndefault_order_by: tags\nfields:\n  tags: {name: tags, query: {type: Array}}\n";
+
+    fn problems(yaml: &str) -> Vec<SchemaProblemKind> {
+        let schema: Schema = serde_yaml::from_str(yaml).unwrap();
+        match schema.validate() {
+            Ok(()) => Vec::new(),
+            Err(problems) => problems.into_iter().map(|p| p.kind).collect(),
+        }
+    }
+
+    #[test]
+    fn default_order_must_be_returnable() {
+        let fields = "fields:\n  n: {name: n, query: {type: NumericTag}}\n  secret: {name: secret, hidden: true, query: {type: NumericTag}}\n  internal: {name: internal, sortable: false, query: {type: NumericTag}}\n";
+        let order = |by: &str| problems(&format!("table: t\ndefault_order_by: {}\n{}", by, fields));
+
+        assert_eq!(order("n"), vec![]);
+        assert_eq!(order("internal"), vec![]);
+        assert_eq!(
+            order("n,-secret"),
+            vec![SchemaProblemKind::HiddenSortField("n,-secret".to_owned())]
+        );
+    }
+
+    #[test]
+    fn precision_is_capped() {
+        let precision = |p: u32| {
+            problems(&format!(
+                "table: t\ndefault_order_by: r\nfields:\n  r: {{name: r, query: {{type: NumericTag, number: Decimal, precision: {}}}}}\n",
+                p
+            ))
+        };
+
+        assert_eq!(precision(MAX_PRECISION), vec![]);
+        assert_eq!(
+            precision(40),
+            vec![SchemaProblemKind::ExcessivePrecision(40)]
+        );
+    }
+
+    #[test]
+    fn comma_separators_clash_with_arrays() {
+        assert_eq!(problems(FIELDS), vec![]);
+        assert_eq!(
+            problems(&format!("{}value_lists: {{and: ';'}}\n", FIELDS)),
+            vec![]
+        );
+        assert_eq!(
+            problems(&format!("{}value_lists: {{and: ','}}\n", FIELDS)),
+            vec![SchemaProblemKind::CommaSeparator]
+        );
+        assert_eq!(
+            problems(&format!("{}value_lists: {{or: '_,_'}}\n", FIELDS)),
+            vec![SchemaProblemKind::CommaSeparator]
+        );
+        assert_eq!(
+            problems("table: t\ndefault_order_by: tag\nfields:\n  tag: {name: tag, query: {type: StringTag}}\nvalue_lists: {and: ','}\n"),
+            vec![]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use super::*;
+
+use serde_json::Value;
+
+impl Schema {
+    /// A copy of this schema that applies field flags according to `visibility`.
+    pub fn with_visibility(&self, visibility: Visibility) -> Schema {
+        Schema {
+            visibility,
+            ..self.clone()
+        }
+    }
+
+    pub fn filterable(&self, field: &Field) -> bool {
+        self.visibility == Visibility::Internal || (field.filterable && !field.hidden)
+    }
+
+    pub fn sortable(&self, field: &Field) -> bool {
+        self.visibility == Visibility::Internal || (field.sortable && !field.hidden)
+    }
+
+    pub fn returnable(&self, field: &Field) -> bool {
+        self.visibility == Visibility::Internal || (field.returnable && !field.hidden)
+    }
+}
+
+// a full-text field searches its targets' text, so it's only as filterable as they are. targets outside the schema are fine
+pub(crate) fn targets_filterable(schema: &Schema, name: &str, query: &FieldQuery) -> bool {
+    match query {
+        FieldQuery::Fulltext {
+            target, targets, ..
+        } => fulltext_targets(name, target.as_deref(), targets)
+            .iter()
+            .all(
+                |t| match field_for_path(schema, &FieldPath::new(&t.field)) {
+                    Some(f) => schema.filterable(f),
+                    None => true,
+                },
+            ),
+        FieldQuery::Not(inner) => targets_filterable(schema, name, inner),
+        _ => true,
+    }
+}
+
+/// Takes every field that isn't returnable out of a result.
+pub(crate) fn strip_unreturnable(schema: &Schema, doc: &mut Value) {
+    for (name, field) in &schema.fields {
+        if !schema.returnable(field) {
+            remove_path(doc, &FieldPath::new(name).0);
+        }
+    }
+}
+
+fn remove_path(doc: &mut Value, path: &[String]) {
+    match path {
+        [] => {}
+        [last] => {
+            if let Some(obj) = doc.as_object_mut() {
+                obj.remove(last);
+            }
+        }
+        [first, rest @ ..] => {
+            if let Some(child) = doc.get_mut(first) {
+                remove_path(child, rest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = "table: t\ndefault_order_by: n\nfields:\n  n: {name: n, query: {type: NumericTag}}\n  secret: {name: secret, hidden: true, query: {type: NumericTag}}\n  unsorted: {name: unsorted, sortable: false, query: {type: NumericTag}}\n  unreturned: {name: unreturned, returnable: false, query: {type: NumericTag}}\n  meta: {name: meta, query: {type: Nested}}\n";
+
+    fn sort(schema: &Schema, sortby: &str) -> Result<Sort, CompassError> {
+        Sort::from_params(
+            schema,
+            &SearchParams::parse(&format!("sortby={}", sortby)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn public_sorting_needs_sortable_returnable_fields() {
+        let schema = Schema::from_yaml_str(SCHEMA).unwrap();
+        assert!(sort(&schema, "n,-meta.x").is_ok());
+        for sortby in ["secret", "unsorted", "unreturned", "n,unreturned"] {
+            assert!(matches!(
+                sort(&schema, sortby),
+                Err(CompassError::FieldNotFound)
+            ));
+        }
+
+        let internal = schema.with_visibility(Visibility::Internal);
+        for sortby in ["secret", "unsorted", "unreturned"] {
+            assert!(sort(&internal, sortby).is_ok());
+        }
+    }
+
+    #[test]
+    fn default_order_can_use_unsortable_fields() {
+        let mut schema = Schema::from_yaml_str(SCHEMA).unwrap();
+        schema.default_order_by = "unsorted".to_owned();
+        assert!(Sort::from_params(&schema, &SearchParams::default()).is_ok());
+
+        // validation keeps this out of loaded schemas, but it'd leak through cursors
+        schema.default_order_by = "secret".to_owned();
+        assert!(matches!(
+            Sort::from_params(&schema, &SearchParams::default()),
+            Err(CompassError::FieldNotFound)
+        ));
+    }
+
+    #[test]
+    fn fulltext_fields_are_only_as_filterable_as_their_targets() {
+        let schema = Schema::from_yaml_str(
+            "table: t\ndefault_order_by: title\nfields:\n  title: {name: title, query: {type: StringTag}}\n  notes: {name: notes, hidden: true, query: {type: StringTag}}\n  search: {name: search, query: {type: Fulltext, lang: english, target: notes}}\n  all: {name: all, query: {type: Fulltext, lang: english, targets: [{field: title}, {field: notes}]}}\n  open: {name: open, query: {type: Fulltext, lang: english, targets: [{field: title}, {field: body}]}}\n",
+        )
+        .unwrap();
+
+        assert!(resolve_field(&schema, "search").is_none());
+        assert!(resolve_field(&schema, "search!").is_none());
+        assert!(resolve_field(&schema, "all").is_none());
+        assert!(resolve_field(&schema, "open").is_some());
+
+        let params = SearchParams::parse("search=secret&all=secret&sortby=relevance").unwrap();
+        assert_eq!(
+            Query::from_params(&schema, &params).unwrap(),
+            Query::And(Vec::new())
+        );
+        assert!(matches!(
+            Query::parse_expression(&schema, "search:secret"),
+            Err(CompassError::InvalidExpression(_))
+        ));
+
+        let internal = schema.with_visibility(Visibility::Internal);
+        assert!(resolve_field(&internal, "search").is_some());
+        assert!(resolve_field(&internal, "all").is_some());
+    }
+}